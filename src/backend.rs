@@ -0,0 +1,127 @@
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::display::{Display, DisplayConfiguration, Rectangle};
+
+#[cfg(windows)]
+mod win32;
+
+#[cfg(windows)]
+pub use win32::Win32;
+
+/// Environment variable other platforms read the display layout file from
+pub const LAYOUT_VARIABLE: &str = "WALLPAPER_ALIGNER_LAYOUT";
+
+/// A source of the display layout, everything after it works the same on every platform
+pub trait DisplayBackend {
+    /// Lists the displays with their positions on the virtual desktop, in no particular order
+    fn detect(&self) -> io::Result<DisplayConfiguration>;
+}
+
+/// Reads the layout from a JSON file written with --displays-json, e.g. on another machine,
+/// or from a file in the format of `ManualLayout`
+pub struct LayoutFile {
+    pub path: PathBuf,
+}
+
+impl DisplayBackend for LayoutFile {
+    fn detect(&self) -> io::Result<DisplayConfiguration> {
+        let text = std::fs::read_to_string(&self.path)?;
+        if !text.trim_start().starts_with('{') {
+            return ManualLayout::from_str(&text)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+                .detect();
+        }
+        let config: DisplayConfiguration = serde_json::from_str(&text)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        // The displays are what matters, the bounds may be stale after editing the file by hand
+        Ok(DisplayConfiguration::from_displays(config.displays))
+    }
+}
+
+/// A layout given by hand as x,y,width,height and an optional name for every display,
+/// separated by semicolons or new lines, e.g. `0,0,1920,1080,Left;1920,0,2560,1440,Right`
+#[derive(Debug, Clone)]
+pub struct ManualLayout {
+    pub displays: Vec<Display>,
+}
+
+impl FromStr for ManualLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut displays = Vec::new();
+        let specs = s.split([';', '\n']).map(str::trim);
+        for spec in specs.filter(|spec| !spec.is_empty()) {
+            let number = displays.len() + 1;
+            let parts: Vec<&str> = spec.splitn(5, ',').map(str::trim).collect();
+            let (Some(x), Some(y), Some(width), Some(height)) = (
+                parts.first().and_then(|x| x.parse::<i32>().ok()),
+                parts.get(1).and_then(|y| y.parse::<i32>().ok()),
+                parts.get(2).and_then(|width| width.parse::<u32>().ok()),
+                parts.get(3).and_then(|height| height.parse::<u32>().ok()),
+            ) else {
+                return Err(format!(
+                    "Expected display {} as x,y,width,height and an optional name, got '{}'",
+                    number, spec
+                ));
+            };
+            if width == 0 || height == 0 {
+                return Err(format!(
+                    "Display {} must be at least one pixel wide and high",
+                    number
+                ));
+            }
+            displays.push(Display {
+                name: parts
+                    .get(4)
+                    .map_or_else(|| format!("Display {}", number), |name| name.to_string()),
+                bounds: Rectangle::from_xywh(x, y, width, height),
+                dpi: 96,
+                // Windows always puts the primary display at the origin
+                primary: x == 0 && y == 0,
+            });
+        }
+        if displays.is_empty() {
+            return Err("Expected at least one display in the layout".to_owned());
+        }
+        Ok(ManualLayout { displays })
+    }
+}
+
+impl DisplayBackend for ManualLayout {
+    fn detect(&self) -> io::Result<DisplayConfiguration> {
+        Ok(DisplayConfiguration::from_displays(self.displays.clone()))
+    }
+}
+
+/// Returns the backend of the platform the program was built for
+#[cfg(windows)]
+pub fn system(debug: bool) -> Box<dyn DisplayBackend> {
+    Box::new(Win32 { debug })
+}
+
+/// Returns the backend of the platform the program was built for. Displays can't be detected
+/// here yet, so the layout comes from the file named by the `LAYOUT_VARIABLE` variable
+#[cfg(not(windows))]
+pub fn system(_debug: bool) -> Box<dyn DisplayBackend> {
+    struct Unsupported;
+
+    impl DisplayBackend for Unsupported {
+        fn detect(&self) -> io::Result<DisplayConfiguration> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "displays can only be detected on Windows, set {} to a layout file",
+                    LAYOUT_VARIABLE
+                ),
+            ))
+        }
+    }
+
+    match std::env::var_os(LAYOUT_VARIABLE) {
+        Some(path) => Box::new(LayoutFile { path: path.into() }),
+        None => Box::new(Unsupported),
+    }
+}
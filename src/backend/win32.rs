@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io;
+use std::os::windows::prelude::OsStringExt;
+
+use colored::Colorize;
+use windows::Win32::Devices::Display::{
+    DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME, DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME, DISPLAYCONFIG_DEVICE_INFO_HEADER,
+    DISPLAYCONFIG_MODE_INFO, DISPLAYCONFIG_PATH_INFO,
+    DISPLAYCONFIG_SOURCE_DEVICE_NAME, DISPLAYCONFIG_TARGET_DEVICE_NAME, DisplayConfigGetDeviceInfo,
+    GetDisplayConfigBufferSizes, QDC_ONLY_ACTIVE_PATHS, QDC_VIRTUAL_MODE_AWARE,
+    QueryDisplayConfig,
+};
+use windows::Win32::Foundation::{BOOL, LPARAM, RECT, TRUE, WIN32_ERROR};
+use windows::Win32::Graphics::Gdi::{
+    EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFO, MONITORINFOEXW,
+    MONITORINFOF_PRIMARY,
+};
+use windows::Win32::UI::HiDpi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI};
+
+use super::DisplayBackend;
+use crate::display::{Display, DisplayConfiguration, Rectangle};
+
+/// Detects displays through the Win32 API
+pub struct Win32 {
+    /// Print how display names are resolved
+    pub debug: bool,
+}
+
+impl DisplayBackend for Win32 {
+    fn detect(&self) -> io::Result<DisplayConfiguration> {
+        Ok(get_display_configuration(self.debug)?)
+    }
+}
+
+fn get_display_configuration(debug: bool) -> windows::core::Result<DisplayConfiguration> {
+    unsafe extern "system" fn callback(
+        monitor: HMONITOR,
+        _: HDC,
+        rect_ptr: *mut RECT,
+        data: LPARAM,
+    ) -> BOOL {
+        let rect = *rect_ptr;
+        let data = data.0 as *mut (Vec<Display>, HashMap<String, String>, bool);
+        let displays = &mut (*data).0;
+
+        let mut monitor_info: MONITORINFOEXW = std::mem::zeroed();
+        monitor_info.monitorInfo.cbSize = size_of::<MONITORINFOEXW>() as u32;
+        let monitor_info_exw_ptr = &mut monitor_info as *mut _ as *mut MONITORINFO;
+
+        let name = match GetMonitorInfoW(monitor, monitor_info_exw_ptr).ok() {
+            Ok(_) => match convert_string(&monitor_info.szDevice) {
+                Some(str) => match (*data).1.get(&str) {
+                    Some(name) => name.to_owned(),
+                    None => {
+                        if (*data).2 {
+                            let record = serde_json::json!({
+                                "gdi_device_name": str,
+                                "friendly_name": null,
+                                "errors": ["No display path has this GDI device name"],
+                            });
+                            eprintln!("{}", record);
+                        }
+                        "Unknown".to_owned()
+                    }
+                },
+                None => "Unknown".to_owned(),
+            },
+            Err(err) => {
+                eprintln!("{} Unable to get monitor info: {}", "!".yellow(), err);
+                "Unknown".to_owned()
+            }
+        };
+        // Stays zeroed if the monitor info couldn't be read
+        let primary = monitor_info.monitorInfo.dwFlags & MONITORINFOF_PRIMARY != 0;
+
+        let mut dpi = (0u32, 0u32);
+        if let Err(err) = GetDpiForMonitor(monitor, MDT_EFFECTIVE_DPI, &mut dpi.0, &mut dpi.1) {
+            eprintln!("{} Unable to get monitor DPI: {}", "!".yellow(), err);
+            dpi.0 = 96;
+        }
+
+        displays.push(Display {
+            name,
+            bounds: rect.into(),
+            dpi: dpi.0,
+            primary,
+        });
+
+        TRUE
+    }
+
+    let names = get_monitor_names(debug);
+
+    let mut data = (Vec::new(), names, debug);
+    unsafe {
+        EnumDisplayMonitors(
+            None,
+            None,
+            Some(callback),
+            LPARAM(&mut data as *mut _ as isize),
+        )
+    }
+    .ok()?;
+    // Displays don't have to touch the origin, so the bounds only come from the displays
+    Ok(DisplayConfiguration::from_displays(data.0))
+}
+
+fn get_monitor_names(debug: bool) -> HashMap<String, String> {
+    let flags = QDC_ONLY_ACTIVE_PATHS | QDC_VIRTUAL_MODE_AWARE;
+    let mut path_count = 0u32;
+    let mut mode_count = 0u32;
+    match unsafe {
+        GetDisplayConfigBufferSizes(flags, &mut path_count as *mut _, &mut mode_count as *mut _)
+    }
+    .ok()
+    {
+        Ok(_) => {}
+        Err(err) => {
+            eprintln!(
+                "{} Unable to get display configuration buffer sizes: {}",
+                "!".yellow(),
+                err
+            );
+            return HashMap::new();
+        }
+    }
+
+    let mut paths: Vec<DISPLAYCONFIG_PATH_INFO> = Vec::with_capacity(path_count as usize);
+    let mut modes: Vec<DISPLAYCONFIG_MODE_INFO> = Vec::with_capacity(mode_count as usize);
+    unsafe {
+        match QueryDisplayConfig(
+            flags,
+            &mut path_count as *mut _,
+            paths.as_mut_ptr(),
+            &mut mode_count as *mut _,
+            modes.as_mut_ptr(),
+            None,
+        )
+        .ok()
+        {
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("{} Unable to query display config: {}", "!".yellow(), err);
+                return HashMap::new();
+            }
+        }
+        paths.set_len(path_count as usize);
+        modes.set_len(mode_count as usize);
+    }
+
+    let mut result: HashMap<String, String> = HashMap::with_capacity(path_count as usize);
+
+    for (idx, path) in paths.iter().enumerate() {
+        let names = path_names(path);
+        if debug {
+            let record = serde_json::json!({
+                "path": idx,
+                "source_id": path.sourceInfo.id,
+                "target_id": path.targetInfo.id,
+                "adapter_id": format!(
+                    "{:08X}{:08X}",
+                    path.targetInfo.adapterId.HighPart, path.targetInfo.adapterId.LowPart
+                ),
+                "gdi_device_name": names.gdi_device_name,
+                "friendly_name": names.friendly_name,
+                "errors": names.errors,
+            });
+            eprintln!("{}", record);
+        } else {
+            for error in &names.errors {
+                eprintln!("{} {}", "!".yellow(), error);
+            }
+        }
+
+        if let (Some(gdi_device_name), Some(friendly_name)) =
+            (names.gdi_device_name, names.friendly_name)
+        {
+            result.insert(gdi_device_name, friendly_name);
+        }
+    }
+
+    result
+}
+
+/// Names Windows resolves for the source and the target of a display path
+#[derive(Default)]
+struct PathNames {
+    /// Name of the source, e.g. `\\.\DISPLAY1`, which monitors are enumerated by
+    gdi_device_name: Option<String>,
+    /// Name of the monitor the target shows on, e.g. `DELL U2720Q`
+    friendly_name: Option<String>,
+    /// Why either name couldn't be resolved
+    errors: Vec<String>,
+}
+
+fn path_names(path: &DISPLAYCONFIG_PATH_INFO) -> PathNames {
+    let mut names = PathNames::default();
+
+    unsafe {
+        let mut target_name: DISPLAYCONFIG_TARGET_DEVICE_NAME = std::mem::zeroed();
+        target_name.header = DISPLAYCONFIG_DEVICE_INFO_HEADER {
+            r#type: DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+            id: path.targetInfo.id,
+            adapterId: path.targetInfo.adapterId,
+            size: size_of::<DISPLAYCONFIG_TARGET_DEVICE_NAME>() as u32,
+        };
+        let device_name_header_ptr =
+            &mut target_name as *mut _ as *mut DISPLAYCONFIG_DEVICE_INFO_HEADER;
+
+        match WIN32_ERROR(DisplayConfigGetDeviceInfo(device_name_header_ptr) as u32).ok() {
+            Ok(_) => match convert_string(&target_name.monitorFriendlyDeviceName) {
+                Some(str) => names.friendly_name = Some(str),
+                None => names
+                    .errors
+                    .push("Unable to parse target friendly name to a UTF-8 string".to_owned()),
+            },
+            Err(err) => names
+                .errors
+                .push(format!("Unable to get target name: {}", err)),
+        }
+    }
+
+    unsafe {
+        let mut source_name: DISPLAYCONFIG_SOURCE_DEVICE_NAME = std::mem::zeroed();
+        source_name.header = DISPLAYCONFIG_DEVICE_INFO_HEADER {
+            r#type: DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME,
+            size: size_of::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>() as u32,
+            adapterId: path.targetInfo.adapterId,
+            id: path.sourceInfo.id,
+        };
+        let adapter_name_header_ptr =
+            &mut source_name as *mut _ as *mut DISPLAYCONFIG_DEVICE_INFO_HEADER;
+
+        match WIN32_ERROR(DisplayConfigGetDeviceInfo(adapter_name_header_ptr) as u32).ok() {
+            Ok(_) => match convert_string(&source_name.viewGdiDeviceName) {
+                Some(str) => names.gdi_device_name = Some(str),
+                None => names
+                    .errors
+                    .push("Unable to parse source name to a UTF-8 string".to_owned()),
+            },
+            Err(err) => names
+                .errors
+                .push(format!("Unable to get source name: {}", err)),
+        }
+    }
+
+    names
+}
+
+fn convert_string(vec: &[u16]) -> Option<String> {
+    let os_string = match vec.iter().position(|c| *c == 0) {
+        Some(len) => OsString::from_wide(&vec[0..len]),
+        None => OsString::from_wide(&vec[0..vec.len()]),
+    };
+    os_string.to_str().map(|s| s.to_owned())
+}
+
+impl From<RECT> for Rectangle {
+    fn from(value: RECT) -> Self {
+        Rectangle {
+            min_x: value.left,
+            max_x: value.right,
+            min_y: value.top,
+            max_y: value.bottom,
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HEADER_SIZE: usize = 36;
+const FOLDER_SIZE: usize = 8;
+const FILE_ENTRY_SIZE: usize = 16;
+const DATA_HEADER_SIZE: usize = 8;
+/// Largest amount of data a single CFDATA block may hold
+const BLOCK_SIZE: usize = 32768;
+/// File name is UTF-8 rather than the current code page
+const ATTRIBUTE_NAME_IS_UTF: u16 = 0x80;
+
+/// Builds an uncompressed cabinet archive holding `files` as (name, contents) pairs.
+/// Names may contain `\` to place files in subdirectories
+pub fn build(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let file_entries_size: usize = files
+        .iter()
+        .map(|(name, _)| FILE_ENTRY_SIZE + name.len() + 1)
+        .sum();
+    let data_size: usize = files.iter().map(|(_, contents)| contents.len()).sum();
+    let block_count = data_size.div_ceil(BLOCK_SIZE);
+    let files_offset = HEADER_SIZE + FOLDER_SIZE;
+    let data_offset = files_offset + file_entries_size;
+    let cabinet_size = data_offset + block_count * DATA_HEADER_SIZE + data_size;
+    let (date, time) = dos_timestamp(SystemTime::now());
+
+    let mut cab = Vec::with_capacity(cabinet_size);
+    cab.extend_from_slice(b"MSCF");
+    cab.extend_from_slice(&0u32.to_le_bytes());
+    cab.extend_from_slice(&(cabinet_size as u32).to_le_bytes());
+    cab.extend_from_slice(&0u32.to_le_bytes());
+    cab.extend_from_slice(&(files_offset as u32).to_le_bytes());
+    cab.extend_from_slice(&0u32.to_le_bytes());
+    cab.extend_from_slice(&[3, 1]);
+    cab.extend_from_slice(&1u16.to_le_bytes());
+    cab.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    cab.extend_from_slice(&0u16.to_le_bytes());
+    cab.extend_from_slice(&0u16.to_le_bytes());
+    cab.extend_from_slice(&0u16.to_le_bytes());
+
+    cab.extend_from_slice(&(data_offset as u32).to_le_bytes());
+    cab.extend_from_slice(&(block_count as u16).to_le_bytes());
+    cab.extend_from_slice(&0u16.to_le_bytes());
+
+    let mut folder_offset = 0u32;
+    for (name, contents) in files {
+        cab.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        cab.extend_from_slice(&folder_offset.to_le_bytes());
+        cab.extend_from_slice(&0u16.to_le_bytes());
+        cab.extend_from_slice(&date.to_le_bytes());
+        cab.extend_from_slice(&time.to_le_bytes());
+        cab.extend_from_slice(&ATTRIBUTE_NAME_IS_UTF.to_le_bytes());
+        cab.extend_from_slice(name.as_bytes());
+        cab.push(0);
+        folder_offset += contents.len() as u32;
+    }
+
+    let data: Vec<u8> = files
+        .iter()
+        .flat_map(|(_, contents)| contents.iter().copied())
+        .collect();
+    for block in data.chunks(BLOCK_SIZE) {
+        let sizes = [
+            (block.len() as u16).to_le_bytes(),
+            (block.len() as u16).to_le_bytes(),
+        ]
+        .concat();
+        cab.extend_from_slice(&checksum(&sizes, checksum(block, 0)).to_le_bytes());
+        cab.extend_from_slice(&sizes);
+        cab.extend_from_slice(block);
+    }
+    cab
+}
+
+/// CFDATA checksum as described by the cabinet file format specification
+fn checksum(data: &[u8], seed: u32) -> u32 {
+    let mut sum = seed;
+    let mut words = data.chunks_exact(4);
+    for word in &mut words {
+        sum ^= u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    let tail = words
+        .remainder()
+        .iter()
+        .fold(0u32, |tail, byte| (tail << 8) | *byte as u32);
+    sum ^ tail
+}
+
+/// Converts `time` into MS-DOS (date, time) fields, in UTC
+fn dos_timestamp(time: SystemTime) -> (u16, u16) {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let seconds_of_day = seconds % 86400;
+    let date = (((year.clamp(1980, 2107) - 1980) as u16) << 9) | ((month as u16) << 5) | day as u16;
+    let time = ((seconds_of_day / 3600) << 11)
+        | (((seconds_of_day / 60) % 60) << 5)
+        | ((seconds_of_day % 60) / 2);
+    (date, time as u16)
+}
+
+/// Turns days since 1970-01-01 into a (year, month, day) date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(cab: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([cab[offset], cab[offset + 1]])
+    }
+
+    fn u32_at(cab: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(cab[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn two_file_cabinet_has_the_expected_layout() {
+        let cab = build(&[("a.txt", b"hello"), (r"dir\b.bin", b"world!!")]);
+        // Header
+        assert_eq!(cab[..4], *b"MSCF");
+        assert_eq!(u32_at(&cab, 8) as usize, cab.len());
+        assert_eq!(u32_at(&cab, 16), 44);
+        assert_eq!(cab[24..26], [3, 1]);
+        assert_eq!(u16_at(&cab, 26), 1);
+        assert_eq!(u16_at(&cab, 28), 2);
+        // The only folder, stored without compression
+        let data_offset = 44 + (FILE_ENTRY_SIZE + 6) + (FILE_ENTRY_SIZE + 10);
+        assert_eq!(u32_at(&cab, 36) as usize, data_offset);
+        assert_eq!(u16_at(&cab, 40), 1);
+        assert_eq!(u16_at(&cab, 42), 0);
+        // Files, one after the other in the folder
+        assert_eq!(u32_at(&cab, 44), 5);
+        assert_eq!(u32_at(&cab, 48), 0);
+        assert_eq!(u16_at(&cab, 52), 0);
+        assert_eq!(u16_at(&cab, 58), ATTRIBUTE_NAME_IS_UTF);
+        assert_eq!(cab[60..66], *b"a.txt\0");
+        assert_eq!(u32_at(&cab, 66), 7);
+        assert_eq!(u32_at(&cab, 70), 5);
+        assert_eq!(cab[82..92], *b"dir\\b.bin\0");
+        // A single data block
+        let sizes = &cab[data_offset + 4..data_offset + 8];
+        assert_eq!(u16_at(sizes, 0), 12);
+        assert_eq!(u16_at(sizes, 2), 12);
+        let data = &cab[data_offset + 8..];
+        assert_eq!(data, b"helloworld!!");
+        assert_eq!(
+            u32_at(&cab, data_offset),
+            checksum(sizes, checksum(data, 0))
+        );
+    }
+
+    #[test]
+    fn checksum_matches_the_specification() {
+        // Whole little-endian words are XORed, the 1 to 3 bytes left over count big-endian
+        assert_eq!(checksum(b"ABCDEFG", 0), 0x44060406);
+        assert_eq!(checksum(b"ABCD", 0), 0x44434241);
+        assert_eq!(checksum(&[1], 0), 0x01);
+        assert_eq!(checksum(&[1, 2], 0), 0x0102);
+        assert_eq!(checksum(&[1, 2, 3], 0), 0x010203);
+        assert_eq!(checksum(b"", 0x12345678), 0x12345678);
+        assert_eq!(checksum(b"ABCD", 0x44434241), 0);
+    }
+
+    #[test]
+    fn data_is_split_into_32k_blocks() {
+        let contents: Vec<u8> = (0..BLOCK_SIZE + 100).map(|i| i as u8).collect();
+        let cab = build(&[("big.bin", &contents)]);
+        assert_eq!(u32_at(&cab, 8) as usize, cab.len());
+        let data_offset = u32_at(&cab, 36) as usize;
+        assert_eq!(u16_at(&cab, 40), 2);
+
+        let first = data_offset;
+        assert_eq!(u16_at(&cab, first + 4) as usize, BLOCK_SIZE);
+        assert_eq!(u16_at(&cab, first + 6) as usize, BLOCK_SIZE);
+        let second = first + DATA_HEADER_SIZE + BLOCK_SIZE;
+        assert_eq!(u16_at(&cab, second + 4), 100);
+        assert_eq!(u16_at(&cab, second + 6), 100);
+        assert_eq!(second + DATA_HEADER_SIZE + 100, cab.len());
+
+        let stored = [
+            &cab[first + DATA_HEADER_SIZE..second],
+            &cab[second + DATA_HEADER_SIZE..],
+        ]
+        .concat();
+        assert_eq!(stored, contents);
+        for block in [first, second] {
+            let sizes = &cab[block + 4..block + 8];
+            let size = u16_at(sizes, 0) as usize;
+            let data = &cab[block + DATA_HEADER_SIZE..block + DATA_HEADER_SIZE + size];
+            assert_eq!(u32_at(&cab, block), checksum(sizes, checksum(data, 0)));
+        }
+    }
+}
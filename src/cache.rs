@@ -0,0 +1,200 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use image::RgbImage;
+
+use crate::Resized;
+
+/// Width, height, source pixels, used pixels and the length of the warnings that precede
+/// the warnings and the raw RGB data
+const HEADER_LENGTH: usize = 28;
+
+/// Bump when the layout of cache files changes, so files of older versions are not read
+const FORMAT_VERSION: u32 = 2;
+
+/// Bytes the cache may take up, about 20 resized 4K images. The least recently used files are
+/// removed to stay below it
+const MAX_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Where resized images of earlier runs are kept, e.g. %TEMP%\wallpaper-aligner
+fn directory() -> PathBuf {
+    std::env::temp_dir().join("wallpaper-aligner")
+}
+
+fn path(key: &str) -> PathBuf {
+    // FNV-1a, like the layout fingerprint
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in FORMAT_VERSION.to_le_bytes().iter().chain(key.as_bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    directory().join(format!("{:016x}.bin", hash))
+}
+
+/// Reads the image stored under `key`, if there is an intact one
+pub fn load(key: &str) -> Option<Resized> {
+    let path = path(key);
+    let data = fs::read(&path).ok()?;
+    // The key is stored in front of the image, so hash collisions are caught
+    let data = data.strip_prefix(key.as_bytes())?.strip_prefix(b"\n")?;
+    if data.len() < HEADER_LENGTH {
+        return None;
+    }
+    let (header, data) = data.split_at(HEADER_LENGTH);
+    let width = u32::from_le_bytes(header[0..4].try_into().ok()?);
+    let height = u32::from_le_bytes(header[4..8].try_into().ok()?);
+    let warnings_length = u32::from_le_bytes(header[24..28].try_into().ok()?) as usize;
+    if data.len() < warnings_length {
+        return None;
+    }
+    let (warnings, pixels) = data.split_at(warnings_length);
+    let warnings = std::str::from_utf8(warnings).ok()?;
+    let image = RgbImage::from_raw(width, height, pixels.to_vec())?;
+    // Files are pruned oldest first, so the ones still in use are marked as new
+    if let Ok(file) = fs::File::options().write(true).open(&path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+    Some(Resized {
+        image,
+        source_pixels: u64::from_le_bytes(header[8..16].try_into().ok()?),
+        used_pixels: u64::from_le_bytes(header[16..24].try_into().ok()?),
+        // Warnings about the image itself, e.g. that it is distorted, apply to every run
+        warnings: warnings.lines().map(str::to_owned).collect(),
+    })
+}
+
+/// Stores `resized` under `key` in the temporary directory, making room for it if the cache
+/// grew too large
+pub fn store(key: &str, resized: &Resized) -> io::Result<()> {
+    let path = path(key);
+    let directory = directory();
+    fs::create_dir_all(&directory)?;
+    let pixels = resized.image.as_raw();
+    let warnings = resized.warnings.join("\n");
+    let mut data =
+        Vec::with_capacity(key.len() + 1 + HEADER_LENGTH + warnings.len() + pixels.len());
+    data.extend_from_slice(key.as_bytes());
+    data.push(b'\n');
+    data.extend_from_slice(&resized.image.width().to_le_bytes());
+    data.extend_from_slice(&resized.image.height().to_le_bytes());
+    data.extend_from_slice(&resized.source_pixels.to_le_bytes());
+    data.extend_from_slice(&resized.used_pixels.to_le_bytes());
+    data.extend_from_slice(&(warnings.len() as u32).to_le_bytes());
+    data.extend_from_slice(warnings.as_bytes());
+    data.extend_from_slice(pixels);
+    prune(&directory, MAX_SIZE.saturating_sub(data.len() as u64))?;
+    fs::write(path, data)
+}
+
+/// Removes the least recently used files from `directory` until the rest take `max_size` bytes
+/// or less
+fn prune(directory: &Path, max_size: u64) -> io::Result<()> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((modified, metadata.len(), entry.path()));
+        }
+    }
+    let mut size: u64 = files.iter().map(|(_, length, _)| length).sum();
+    files.sort();
+    for (_, length, path) in files {
+        if size <= max_size {
+            break;
+        }
+        // Another process may have removed the file already
+        if fs::remove_file(path).is_ok() {
+            size -= length;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+
+    fn resized() -> Resized {
+        Resized {
+            image: RgbImage::from_fn(5, 3, |x, y| Rgb([x as u8, y as u8, 7])),
+            source_pixels: 1000,
+            used_pixels: 800,
+            warnings: vec!["first".to_owned(), "second".to_owned()],
+        }
+    }
+
+    /// A key no other test or run uses
+    fn key(name: &str) -> String {
+        format!("test {} {}", std::process::id(), name)
+    }
+
+    #[test]
+    fn stored_images_load_unchanged() {
+        let key = key("round trip");
+        store(&key, &resized()).unwrap();
+        let loaded = load(&key).unwrap();
+        fs::remove_file(path(&key)).unwrap();
+        let expected = resized();
+        assert_eq!(loaded.image, expected.image);
+        assert_eq!(loaded.source_pixels, expected.source_pixels);
+        assert_eq!(loaded.used_pixels, expected.used_pixels);
+        assert_eq!(loaded.warnings, expected.warnings);
+    }
+
+    #[test]
+    fn truncated_files_are_not_loaded() {
+        let key = key("truncated");
+        store(&key, &resized()).unwrap();
+        let data = fs::read(path(&key)).unwrap();
+        fs::write(path(&key), &data[..data.len() - 1]).unwrap();
+        let loaded = load(&key);
+        fs::write(path(&key), &data[..key.len() + 10]).unwrap();
+        let header_only = load(&key);
+        fs::remove_file(path(&key)).unwrap();
+        assert!(loaded.is_none());
+        assert!(header_only.is_none());
+    }
+
+    #[test]
+    fn files_of_other_keys_are_not_loaded() {
+        // Stands for a hash collision, where the file belongs to another key
+        let (key, other) = (key("stored"), key("colliding"));
+        store(&key, &resized()).unwrap();
+        fs::rename(path(&key), path(&other)).unwrap();
+        let loaded = load(&other);
+        fs::remove_file(path(&other)).unwrap();
+        assert!(loaded.is_none());
+    }
+
+    #[test]
+    fn prune_removes_the_oldest_files_first() {
+        let directory = std::env::temp_dir().join(format!(
+            "wallpaper-aligner-test-{}-prune",
+            std::process::id()
+        ));
+        fs::create_dir_all(&directory).unwrap();
+        let now = SystemTime::now();
+        for (name, age) in [("old", 30), ("middle", 20), ("new", 10)] {
+            let path = directory.join(name);
+            fs::write(&path, [0; 100]).unwrap();
+            let modified = now - std::time::Duration::from_secs(age);
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        prune(&directory, 250).unwrap();
+        let exists = |name: &str| directory.join(name).exists();
+        let remaining = (exists("old"), exists("middle"), exists("new"));
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(remaining, (false, true, true));
+    }
+}
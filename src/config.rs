@@ -0,0 +1,24 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use wallpaper_aligner::ResizeMode;
+
+/// Settings read from a TOML file with `--config`
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub output: Option<String>,
+    pub mode: Option<ResizeMode>,
+    pub quality: Option<i32>,
+    /// Images and colors in order of displays, written the same way as on the command line
+    #[serde(default)]
+    pub images: Vec<String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        toml::from_str(&text).map_err(|err| err.to_string())
+    }
+}
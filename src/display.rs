@@ -1,12 +1,22 @@
-use windows::Win32::Foundation::RECT;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DisplayConfiguration {
     pub bounds: Rectangle,
     pub displays: Vec<Display>,
 }
 
 impl DisplayConfiguration {
+    /// Builds a configuration out of `displays`, bounded by the smallest rectangle containing them
+    pub fn from_displays(displays: Vec<Display>) -> DisplayConfiguration {
+        let mut config = DisplayConfiguration {
+            bounds: Rectangle::default(),
+            displays,
+        };
+        config.update_bounds();
+        config
+    }
+
     pub fn normalize(&mut self) -> &mut Self {
         for x in &mut self.displays {
             x.bounds.move_by(-self.bounds.min_x, -self.bounds.min_y);
@@ -21,22 +31,215 @@ impl DisplayConfiguration {
         clone
     }
     
+    /// Multiplies all coordinates by `factor`, so displays that touched still touch
+    pub fn scale(&mut self, factor: f64) -> &mut Self {
+        for display in &mut self.displays {
+            display.bounds.scale(factor);
+        }
+        self.bounds.scale(factor);
+        self
+    }
+
+    /// Recomputes `bounds` as the smallest rectangle containing every display
+    pub fn update_bounds(&mut self) -> &mut Self {
+        let mut displays = self.displays.iter().map(|display| &display.bounds);
+        self.bounds = match displays.next() {
+            Some(first) => displays.fold(first.clone(), |bounds, other| Rectangle {
+                min_x: bounds.min_x.min(other.min_x),
+                max_x: bounds.max_x.max(other.max_x),
+                min_y: bounds.min_y.min(other.min_y),
+                max_y: bounds.max_y.max(other.max_y),
+            }),
+            None => Rectangle::default(),
+        };
+        self
+    }
+
+    /// Sorts displays top-to-bottom by their top edge, then left-to-right by their left edge
+    pub fn sort_by_position(&mut self) -> &mut Self {
+        self.displays
+            .sort_by_key(|display| (display.bounds.min_y, display.bounds.min_x));
+        self
+    }
+
+    /// Sorts displays in reading order (top-to-bottom, then left-to-right) as seen on a wall
+    /// that is physically rotated clockwise by `rotation` degrees
+    pub fn sort_by_rotated_position(&mut self, rotation: u32) -> &mut Self {
+        self.displays.sort_by_key(|display| {
+            let x = display.bounds.min_x + display.bounds.max_x;
+            let y = display.bounds.min_y + display.bounds.max_y;
+            match rotation {
+                90 => (x, -y),
+                180 => (-y, -x),
+                270 => (-x, y),
+                _ => (y, x),
+            }
+        });
+        self
+    }
+
+    /// Returns the display Windows uses as the main one, if it is among the displays
+    pub fn primary(&self) -> Option<&Display> {
+        self.displays.iter().find(|display| display.primary)
+    }
+
+    /// Returns the display showing the pixel at `x`, `y` of the virtual desktop, if any. Where
+    /// displays overlap that is the later one, whose image covers the earlier ones
+    pub fn display_at(&self, x: i32, y: i32) -> Option<&Display> {
+        self.displays
+            .iter()
+            .rev()
+            .find(|display| display.contains_point(x, y))
+    }
+
+    /// Moves the primary display to the front, keeping the others in their current order
+    pub fn move_primary_first(&mut self) -> &mut Self {
+        self.displays.sort_by_key(|display| !display.primary);
+        self
+    }
+
+    /// Returns a stable hash of the layout that changes whenever a display is added, removed,
+    /// renamed, moved or resized
+    pub fn fingerprint(&self) -> String {
+        let mut displays: Vec<String> = self
+            .displays
+            .iter()
+            .map(|display| {
+                let bounds = &display.bounds;
+                format!(
+                    "{}@{},{},{},{}",
+                    display.name, bounds.min_x, bounds.min_y, bounds.max_x, bounds.max_y
+                )
+            })
+            .collect();
+        displays.sort();
+
+        // FNV-1a, since DefaultHasher output may change between Rust versions
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in displays.join(";").bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        format!("{:016x}", hash)
+    }
+
+    /// Lists the index pairs of displays whose bounds overlap, e.g. cloned displays
+    pub fn overlapping_displays(&self) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for (i, display) in self.displays.iter().enumerate() {
+            for (j, other) in self.displays.iter().enumerate().skip(i + 1) {
+                if display.bounds.overlaps(&other.bounds) {
+                    pairs.push((i, j));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// Splits the parts of `bounds` that no display covers into rectangles, e.g. the space between
+    /// displays that aren't flush or next to a shorter display. Gaps in a row are joined
+    pub fn gaps(&self) -> Vec<Rectangle> {
+        // Every display edge is a grid line, so each cell is either inside a display or a gap
+        let grid_lines = |edges: &mut Vec<i32>, min: i32, max: i32| {
+            edges.retain(|edge| (min..=max).contains(edge));
+            edges.extend([min, max]);
+            edges.sort_unstable();
+            edges.dedup();
+        };
+        let mut columns: Vec<_> = self
+            .displays
+            .iter()
+            .flat_map(|display| [display.bounds.min_x, display.bounds.max_x])
+            .collect();
+        grid_lines(&mut columns, self.bounds.min_x, self.bounds.max_x);
+        let mut rows: Vec<_> = self
+            .displays
+            .iter()
+            .flat_map(|display| [display.bounds.min_y, display.bounds.max_y])
+            .collect();
+        grid_lines(&mut rows, self.bounds.min_y, self.bounds.max_y);
+
+        let mut gaps: Vec<Rectangle> = Vec::new();
+        for row in rows.windows(2) {
+            for column in columns.windows(2) {
+                let covered = self
+                    .displays
+                    .iter()
+                    .any(|display| display.bounds.contains(column[0], row[0]));
+                if covered {
+                    continue;
+                }
+                match gaps.last_mut() {
+                    Some(last) if last.min_y == row[0] && last.max_x == column[0] => {
+                        last.max_x = column[1];
+                    }
+                    _ => gaps.push(Rectangle {
+                        min_x: column[0],
+                        max_x: column[1],
+                        min_y: row[0],
+                        max_y: row[1],
+                    }),
+                }
+            }
+        }
+        gaps
+    }
+
     pub fn show_displays(&self) {
         println!("Detected displays ({} total):", self.displays.len());
         for (i, display) in self.displays.iter().enumerate() {
-            let (width, height) = display.bounds.resolution();
-            println!("{}. {} ({}x{})", i + 1, display.name, width, height);
+            let Some((width, height)) = display.bounds.checked_resolution() else {
+                println!("{}. {} (invalid bounds)", i + 1, display.name);
+                continue;
+            };
+            let orientation = if height > width { ", portrait" } else { "" };
+            println!(
+                "{}. {}{} ({}x{}{}, {}% scaling)",
+                i + 1,
+                display.name,
+                if display.primary { "*" } else { "" },
+                width,
+                height,
+                orientation,
+                display.scale_percent()
+            );
+        }
+        if self.primary().is_some() {
+            println!("* primary display");
         }
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Display {
     pub name: String,
     pub bounds: Rectangle,
+    /// Effective DPI Windows scales the interface of this display to, 96 being 100%
+    #[serde(default = "default_dpi")]
+    pub dpi: u32,
+    /// Whether Windows uses this display as the main one
+    #[serde(default)]
+    pub primary: bool,
+}
+
+/// Layouts written by hand may leave out the DPI, which then means no scaling
+fn default_dpi() -> u32 {
+    96
+}
+
+impl Display {
+    /// Interface scaling of this display in percent
+    pub fn scale_percent(&self) -> u32 {
+        (self.dpi * 100 + 48) / 96
+    }
+
+    /// Whether the pixel at `x`, `y` of the virtual desktop is on this display
+    pub fn contains_point(&self, x: i32, y: i32) -> bool {
+        self.bounds.contains(x, y)
+    }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Rectangle {
     pub min_x: i32,
     pub max_x: i32,
@@ -45,9 +248,32 @@ pub struct Rectangle {
 }
 
 impl Rectangle {
+    /// Builds a rectangle of `width` by `height` pixels with its top left corner at `x`, `y`
+    pub fn from_xywh(x: i32, y: i32, width: u32, height: u32) -> Rectangle {
+        Rectangle {
+            min_x: x,
+            max_x: x + width as i32,
+            min_y: y,
+            max_y: y + height as i32,
+        }
+    }
+
+    /// Width and height of this rectangle. The maximum edges must not be below the minimum ones,
+    /// use `checked_resolution` for rectangles that come straight from the system
     pub fn resolution(&self) -> (u32, u32) {
         ((self.max_x - self.min_x) as u32, (self.max_y - self.min_y) as u32)
     }
+
+    /// Width and height of this rectangle, or None if it is empty or inverted
+    pub fn checked_resolution(&self) -> Option<(u32, u32)> {
+        let width = self.max_x - self.min_x;
+        let height = self.max_y - self.min_y;
+        if width > 0 && height > 0 {
+            Some((width as u32, height as u32))
+        } else {
+            None
+        }
+    }
     
     pub fn normalize(&mut self) -> &mut Self {
         self.max_x -= self.min_x;
@@ -76,15 +302,332 @@ impl Rectangle {
         clone.move_by(x, y);
         clone
     }
+
+    pub fn scale(&mut self, factor: f64) -> &mut Self {
+        // Edges are scaled on their own rather than as a size, so shared edges stay shared
+        let scale = |coordinate: i32| (coordinate as f64 * factor).round() as i32;
+        self.min_x = scale(self.min_x);
+        self.max_x = scale(self.max_x).max(self.min_x + 1);
+        self.min_y = scale(self.min_y);
+        self.max_y = scale(self.max_y).max(self.min_y + 1);
+        self
+    }
+
+    /// Width divided by height, 1 for an empty rectangle
+    pub fn aspect_ratio(&self) -> f32 {
+        match self.checked_resolution() {
+            Some((width, height)) => width as f32 / height as f32,
+            None => 1.0,
+        }
+    }
+
+    pub fn area(&self) -> u64 {
+        let (width, height) = self.resolution();
+        width as u64 * height as u64
+    }
+
+    /// Whether the pixel at `x`, `y` lies inside this rectangle
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        (self.min_x..self.max_x).contains(&x) && (self.min_y..self.max_y).contains(&y)
+    }
+
+    /// Places a rectangle of `size` in the middle of this one. Along an axis where it is larger
+    /// than this rectangle it starts at the same edge instead
+    pub fn centered(&self, size: (u32, u32)) -> Rectangle {
+        let (width, height) = self.resolution();
+        let x = self.min_x + (width.saturating_sub(size.0) / 2) as i32;
+        let y = self.min_y + (height.saturating_sub(size.1) / 2) as i32;
+        Rectangle::from_xywh(x, y, size.0, size.1)
+    }
+
+    /// Returns the part of this rectangle that is also covered by `other`, if there is one
+    pub fn intersection(&self, other: &Rectangle) -> Option<Rectangle> {
+        let intersection = Rectangle {
+            min_x: self.min_x.max(other.min_x),
+            max_x: self.max_x.min(other.max_x),
+            min_y: self.min_y.max(other.min_y),
+            max_y: self.max_y.min(other.max_y),
+        };
+        if intersection.min_x < intersection.max_x && intersection.min_y < intersection.max_y {
+            Some(intersection)
+        } else {
+            None
+        }
+    }
+
+    /// Whether this rectangle and `other` share any pixels, touching edges don't count
+    pub fn overlaps(&self, other: &Rectangle) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// Returns the edge of this rectangle that touches `other`, along with the touching span
+    pub fn shared_edge(&self, other: &Rectangle) -> Option<(Edge, i32, i32)> {
+        let vertical_span = (self.min_y.max(other.min_y), self.max_y.min(other.max_y));
+        let horizontal_span = (self.min_x.max(other.min_x), self.max_x.min(other.max_x));
+        let (edge, (start, end)) = if self.min_x == other.max_x {
+            (Edge::Left, vertical_span)
+        } else if self.max_x == other.min_x {
+            (Edge::Right, vertical_span)
+        } else if self.min_y == other.max_y {
+            (Edge::Top, horizontal_span)
+        } else if self.max_y == other.min_y {
+            (Edge::Bottom, horizontal_span)
+        } else {
+            return None;
+        };
+        if start < end {
+            Some((edge, start, end))
+        } else {
+            None
+        }
+    }
+
+    /// Splits this rectangle into a grid of at least `count` cells, listed row by row.
+    /// Without `columns` the cells are kept roughly square
+    pub fn grid(&self, count: usize, columns: Option<usize>) -> Vec<Rectangle> {
+        let (width, height) = self.resolution();
+        let columns = columns
+            .unwrap_or_else(|| {
+                (count as f64 * width as f64 / height as f64).sqrt().round() as usize
+            })
+            .clamp(1, count.max(1));
+        let rows = count.div_ceil(columns);
+        let mut cells = Vec::with_capacity(rows * columns);
+        for row in 0..rows as i64 {
+            for column in 0..columns as i64 {
+                cells.push(Rectangle {
+                    min_x: self.min_x + (width as i64 * column / columns as i64) as i32,
+                    max_x: self.min_x + (width as i64 * (column + 1) / columns as i64) as i32,
+                    min_y: self.min_y + (height as i64 * row / rows as i64) as i32,
+                    max_y: self.min_y + (height as i64 * (row + 1) / rows as i64) as i32,
+                });
+            }
+        }
+        cells
+    }
 }
 
-impl From<RECT> for Rectangle {
-    fn from(value: RECT) -> Self {
-        Rectangle {
-            min_x: value.left,
-            max_x: value.right,
-            min_y: value.top,
-            max_y: value.bottom,
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(name: &str, x: i32, y: i32, width: u32, height: u32) -> Display {
+        Display {
+            name: name.to_owned(),
+            bounds: Rectangle::from_xywh(x, y, width, height),
+            dpi: 96,
+            primary: x == 0 && y == 0,
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bounds_contain_displays_left_of_and_above_the_primary() {
+        let config = DisplayConfiguration::from_displays(vec![
+            display("primary", 0, 0, 2560, 1440),
+            display("left", -1920, 360, 1920, 1080),
+            display("above", 320, -1080, 1920, 1080),
+        ]);
+        assert_eq!(
+            config.bounds,
+            Rectangle::from_xywh(-1920, -1080, 4480, 2520)
+        );
+        assert_eq!(config.bounds.resolution(), (4480, 2520));
+    }
+
+    #[test]
+    fn normalize_moves_the_layout_to_the_origin() {
+        let mut config = DisplayConfiguration::from_displays(vec![
+            display("primary", 0, 0, 2560, 1440),
+            display("left", -1920, 360, 1920, 1080),
+            display("above", 320, -1080, 1920, 1080),
+        ]);
+        config.normalize();
+        assert_eq!(config.bounds, Rectangle::from_xywh(0, 0, 4480, 2520));
+        assert_eq!(
+            config.displays[0].bounds,
+            Rectangle::from_xywh(1920, 1080, 2560, 1440)
+        );
+        assert_eq!(
+            config.displays[1].bounds,
+            Rectangle::from_xywh(0, 1440, 1920, 1080)
+        );
+        assert_eq!(
+            config.displays[2].bounds,
+            Rectangle::from_xywh(2240, 0, 1920, 1080)
+        );
+        // Moving keeps every display its size
+        assert_eq!(config.displays[0].bounds.resolution(), (2560, 1440));
+        assert_eq!(config.displays[1].bounds.resolution(), (1920, 1080));
+    }
+
+    #[test]
+    fn normalize_leaves_a_layout_at_the_origin_alone() {
+        let mut config = DisplayConfiguration::from_displays(vec![
+            display("primary", 0, 0, 1920, 1080),
+            display("right", 1920, 0, 1080, 1920),
+        ]);
+        let before = config.clone();
+        config.normalize();
+        assert_eq!(config.bounds, before.bounds);
+        assert_eq!(config.bounds.resolution(), (3000, 1920));
+        assert_eq!(config.displays[1].bounds, before.displays[1].bounds);
+    }
+
+    #[test]
+    fn intersection_of_partly_overlapping_rectangles() {
+        let first = Rectangle::from_xywh(0, 0, 100, 100);
+        let second = Rectangle::from_xywh(60, -20, 100, 50);
+        let expected = Rectangle::from_xywh(60, 0, 40, 30);
+        assert_eq!(first.intersection(&second), Some(expected.clone()));
+        assert_eq!(second.intersection(&first), Some(expected));
+    }
+
+    #[test]
+    fn intersection_of_nested_rectangles_is_the_inner_one() {
+        let outer = Rectangle::from_xywh(-50, -50, 200, 200);
+        let inner = Rectangle::from_xywh(10, 20, 30, 40);
+        assert_eq!(outer.intersection(&inner), Some(inner.clone()));
+        assert_eq!(inner.intersection(&outer), Some(inner.clone()));
+        assert_eq!(inner.intersection(&inner), Some(inner));
+    }
+
+    #[test]
+    fn disjoint_rectangles_do_not_intersect() {
+        let first = Rectangle::from_xywh(0, 0, 100, 100);
+        let second = Rectangle::from_xywh(200, 200, 100, 100);
+        assert_eq!(first.intersection(&second), None);
+        assert!(!first.overlaps(&second));
+    }
+
+    #[test]
+    fn touching_rectangles_do_not_intersect() {
+        // The maximum edges are exclusive, so rectangles sharing an edge share no pixels
+        let first = Rectangle::from_xywh(0, 0, 1920, 1080);
+        let right = Rectangle::from_xywh(1920, 0, 1920, 1080);
+        let below = Rectangle::from_xywh(0, 1080, 1920, 1080);
+        let corner = Rectangle::from_xywh(1920, 1080, 1920, 1080);
+        assert_eq!(first.intersection(&right), None);
+        assert_eq!(first.intersection(&below), None);
+        assert_eq!(first.intersection(&corner), None);
+        assert!(!first.overlaps(&right));
+    }
+
+    #[test]
+    fn area_is_width_times_height() {
+        assert_eq!(Rectangle::from_xywh(-1920, 0, 1920, 1080).area(), 2_073_600);
+        assert_eq!(Rectangle::from_xywh(0, 0, 0, 1080).area(), 0);
+        // Large enough to overflow 32 bits
+        assert_eq!(
+            Rectangle::from_xywh(0, 0, 100_000, 100_000).area(),
+            10_000_000_000
+        );
+    }
+
+    #[test]
+    fn contains_includes_minimum_and_excludes_maximum_edges() {
+        let rectangle = Rectangle::from_xywh(-10, -20, 30, 40);
+        assert!(rectangle.contains(-10, -20));
+        assert!(rectangle.contains(19, 19));
+        assert!(rectangle.contains(0, 0));
+        assert!(!rectangle.contains(20, 0));
+        assert!(!rectangle.contains(0, 20));
+        assert!(!rectangle.contains(-11, 0));
+        assert!(!rectangle.contains(0, -21));
+    }
+
+    #[test]
+    fn contains_point_excludes_the_maximum_edges() {
+        let display = display("primary", 0, 0, 1920, 1080);
+        assert!(display.contains_point(0, 0));
+        assert!(display.contains_point(1919, 1079));
+        assert!(!display.contains_point(1920, 0));
+        assert!(!display.contains_point(0, 1080));
+        assert!(!display.contains_point(-1, 0));
+    }
+
+    #[test]
+    fn display_at_finds_displays_at_negative_coordinates() {
+        let config = DisplayConfiguration::from_displays(vec![
+            display("primary", 0, 0, 1920, 1080),
+            display("left", -1920, -200, 1920, 1080),
+        ]);
+        assert_eq!(config.display_at(-1, 0).unwrap().name, "left");
+        assert_eq!(config.display_at(-1920, -200).unwrap().name, "left");
+        assert_eq!(config.display_at(0, 0).unwrap().name, "primary");
+        // The shared edge belongs to the display it is the minimum edge of
+        assert_eq!(config.display_at(0, 500).unwrap().name, "primary");
+        assert!(config.display_at(-1921, 0).is_none());
+        assert!(config.display_at(-1, 880).is_none());
+    }
+
+    #[test]
+    fn display_at_finds_nothing_between_displays() {
+        let config = DisplayConfiguration::from_displays(vec![
+            display("primary", 0, 0, 1920, 1080),
+            display("right", 2000, 0, 1080, 1920),
+        ]);
+        assert!(config.display_at(1920, 0).is_none());
+        assert!(config.display_at(1999, 500).is_none());
+        // Below the shorter display, but still inside the bounds
+        assert!(config.display_at(100, 1500).is_none());
+        assert_eq!(config.display_at(2000, 1919).unwrap().name, "right");
+        assert!(config.display_at(3080, 0).is_none());
+    }
+
+    #[test]
+    fn display_at_prefers_the_later_of_overlapping_displays() {
+        let config = DisplayConfiguration::from_displays(vec![
+            display("primary", 0, 0, 1920, 1080),
+            display("clone", 0, 0, 1920, 1080),
+        ]);
+        assert_eq!(config.display_at(100, 100).unwrap().name, "clone");
+    }
+
+    #[test]
+    fn centered_portrait_image_on_landscape_display() {
+        let display = Rectangle::from_xywh(-1920, 0, 1920, 1080);
+        assert_eq!(
+            display.centered((608, 1080)),
+            Rectangle::from_xywh(-1264, 0, 608, 1080)
+        );
+    }
+
+    #[test]
+    fn centered_landscape_image_on_portrait_display() {
+        let display = Rectangle::from_xywh(1920, 0, 1080, 1920);
+        assert_eq!(
+            display.centered((1080, 608)),
+            Rectangle::from_xywh(1920, 656, 1080, 608)
+        );
+    }
+
+    #[test]
+    fn centered_rounds_odd_margins_down() {
+        let display = Rectangle::from_xywh(0, 0, 1920, 1080);
+        assert_eq!(
+            display.centered((1919, 1079)),
+            Rectangle::from_xywh(0, 0, 1919, 1079)
+        );
+        assert_eq!(
+            display.centered((1917, 1080)),
+            Rectangle::from_xywh(1, 0, 1917, 1080)
+        );
+    }
+
+    #[test]
+    fn centered_starts_larger_images_at_the_display_edge() {
+        let display = Rectangle::from_xywh(100, 50, 1920, 1080);
+        assert_eq!(
+            display.centered((2000, 1000)),
+            Rectangle::from_xywh(100, 90, 2000, 1000)
+        );
+    }
+}
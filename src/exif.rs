@@ -0,0 +1,139 @@
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const IFD_ENTRY_SIZE: usize = 12;
+/// Length of an EXIF date like `2024:06:30 18:45:00` without its terminating zero
+const DATE_TIME_LENGTH: usize = 19;
+
+/// Reads when a photo was taken from the DateTimeOriginal tag of its EXIF data, given as the
+/// TIFF structure that follows the `Exif\0\0` header. Returns seconds since 1970-01-01 in the
+/// time zone of the camera, which EXIF doesn't record
+pub fn date_time_original(tiff: &[u8]) -> Option<i64> {
+    let big_endian = match tiff.get(..2)? {
+        b"II" => false,
+        b"MM" => true,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| -> Option<u16> {
+        let bytes = tiff.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let read_u32 = |offset: usize| -> Option<usize> {
+        let bytes = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        } as usize)
+    };
+    // Offset of the value of `tag` in the IFD at `ifd`, values of more than 4 bytes are stored
+    // elsewhere with the entry pointing at them
+    let find = |ifd: usize, tag: u16| -> Option<usize> {
+        let count = read_u16(ifd)? as usize;
+        (0..count)
+            .map(|i| ifd + 2 + i * IFD_ENTRY_SIZE)
+            .find(|&entry| read_u16(entry) == Some(tag))
+            .map(|entry| entry + 8)
+    };
+    if read_u16(2)? != 42 {
+        return None;
+    }
+    let exif_ifd = read_u32(find(read_u32(4)?, TAG_EXIF_IFD)?)?;
+    let date = read_u32(find(exif_ifd, TAG_DATE_TIME_ORIGINAL)?)?;
+    parse_date_time(tiff.get(date..date + DATE_TIME_LENGTH)?)
+}
+
+/// Parses an EXIF date like `2024:06:30 18:45:00` into seconds since 1970-01-01
+fn parse_date_time(text: &[u8]) -> Option<i64> {
+    let text = std::str::from_utf8(text).ok()?;
+    let number = |range: std::ops::Range<usize>| text.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    // Cameras without a set clock write zeros or blanks
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Turns a (year, month, day) date into days since 1970-01-01
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Builds EXIF data whose Exif IFD holds only `date` as DateTimeOriginal
+    pub fn tiff_with_date(date: &str, big_endian: bool) -> Vec<u8> {
+        let u16_bytes = |value: u16| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut tiff = if big_endian { b"MM" } else { b"II" }.to_vec();
+        tiff.extend_from_slice(&u16_bytes(42));
+        tiff.extend_from_slice(&u32_bytes(8));
+        // IFD0 at 8 with only the pointer to the Exif IFD at 26
+        tiff.extend_from_slice(&u16_bytes(1));
+        tiff.extend_from_slice(&u16_bytes(TAG_EXIF_IFD));
+        tiff.extend_from_slice(&u16_bytes(4));
+        tiff.extend_from_slice(&u32_bytes(1));
+        tiff.extend_from_slice(&u32_bytes(26));
+        tiff.extend_from_slice(&u32_bytes(0));
+        // Exif IFD at 26 with the date stored at 44
+        tiff.extend_from_slice(&u16_bytes(1));
+        tiff.extend_from_slice(&u16_bytes(TAG_DATE_TIME_ORIGINAL));
+        tiff.extend_from_slice(&u16_bytes(2));
+        tiff.extend_from_slice(&u32_bytes(20));
+        tiff.extend_from_slice(&u32_bytes(44));
+        tiff.extend_from_slice(&u32_bytes(0));
+        tiff.extend_from_slice(date.as_bytes());
+        tiff.push(0);
+        tiff
+    }
+
+    #[test]
+    fn date_time_original_is_read_in_both_byte_orders() {
+        for big_endian in [false, true] {
+            let tiff = tiff_with_date("2024:06:30 18:45:07", big_endian);
+            assert_eq!(date_time_original(&tiff), Some(1719773107));
+        }
+    }
+
+    #[test]
+    fn unset_and_missing_dates_are_none() {
+        assert_eq!(
+            date_time_original(&tiff_with_date("0000:00:00 00:00:00", false)),
+            None
+        );
+        let tiff = tiff_with_date("2024:06:30 18:45:07", false);
+        assert_eq!(date_time_original(&tiff[..50]), None);
+        assert_eq!(date_time_original(b"not exif"), None);
+    }
+
+    #[test]
+    fn days_are_counted_from_1970() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+    }
+}
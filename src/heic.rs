@@ -0,0 +1,51 @@
+use image::error::{DecodingError, ImageFormatHint};
+use image::{DynamicImage, ImageError, ImageResult, RgbaImage};
+use libheif_rs::{ColorSpace, HeifContext, HeifError, LibHeif, RgbChroma};
+
+/// Brands in the ftyp box of HEIF files, which iPhones save as HEIC
+const BRANDS: [&[u8; 4]; 8] = [
+    b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1",
+];
+
+fn error(err: HeifError) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name("HEIF".to_owned()),
+        err,
+    ))
+}
+
+/// Whether `header` is the start of a HEIF file
+pub fn is_heif(header: &[u8]) -> bool {
+    header.len() >= 12
+        && &header[4..8] == b"ftyp"
+        && BRANDS.iter().any(|brand| header[8..12] == brand[..])
+}
+
+/// Reads the size of the primary image without decoding it
+pub fn dimensions(data: &[u8]) -> ImageResult<(u32, u32)> {
+    let context = HeifContext::read_from_bytes(data).map_err(error)?;
+    let handle = context.primary_image_handle().map_err(error)?;
+    Ok((handle.width(), handle.height()))
+}
+
+/// Decodes the primary image. libheif turns it upright as the container says
+pub fn decode(data: &[u8]) -> ImageResult<DynamicImage> {
+    let context = HeifContext::read_from_bytes(data).map_err(error)?;
+    let handle = context.primary_image_handle().map_err(error)?;
+    let image = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(error)?;
+    let planes = image.planes();
+    let plane = planes
+        .interleaved
+        .expect("RGBA images have a single interleaved plane");
+    // Rows can be padded, so only the pixels of each row are copied
+    let row_length = plane.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_length * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_length]);
+    }
+    let image = RgbaImage::from_raw(plane.width, plane.height, pixels)
+        .expect("every row has the width of the image");
+    Ok(DynamicImage::ImageRgba8(image))
+}
@@ -0,0 +1,127 @@
+const SOI_LENGTH: usize = 2;
+const APP0: [u8; 2] = [0xFF, 0xE0];
+const COM: [u8; 2] = [0xFF, 0xFE];
+const APP1: u8 = 0xE1;
+const SOS: u8 = 0xDA;
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// Longest comment that fits into a single COM segment
+pub const MAX_COMMENT_LENGTH: usize = u16::MAX as usize - 2;
+
+/// Inserts a COM segment after the SOI marker, keeping a JFIF APP0 segment first if there is one
+pub fn insert_comment(jpeg: &[u8], comment: &str) -> Vec<u8> {
+    let mut position = SOI_LENGTH;
+    if jpeg.len() >= position + 4 && jpeg[position..position + 2] == APP0 {
+        let length = u16::from_be_bytes([jpeg[position + 2], jpeg[position + 3]]) as usize;
+        position += 2 + length;
+    }
+
+    let mut result = Vec::with_capacity(jpeg.len() + comment.len() + 4);
+    result.extend_from_slice(&jpeg[..position]);
+    result.extend_from_slice(&COM);
+    result.extend_from_slice(&((comment.len() + 2) as u16).to_be_bytes());
+    result.extend_from_slice(comment.as_bytes());
+    result.extend_from_slice(&jpeg[position..]);
+    result
+}
+
+/// Finds the EXIF data in the APP1 segment of a JPEG, which is enough to pass the start of the file
+/// as the segment comes before the image data. Returns the TIFF structure after the `Exif` header
+pub fn exif(jpeg: &[u8]) -> Option<&[u8]> {
+    if jpeg.get(..SOI_LENGTH)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut position = SOI_LENGTH;
+    loop {
+        let [0xFF, marker] = *jpeg.get(position..position + 2)? else {
+            return None;
+        };
+        if marker == SOS {
+            return None;
+        }
+        let length = u16::from_be_bytes(jpeg.get(position + 2..position + 4)?.try_into().ok()?);
+        let data = jpeg.get(position + 4..position + 2 + length as usize)?;
+        if marker == APP1 && data.starts_with(EXIF_HEADER) {
+            return Some(&data[EXIF_HEADER.len()..]);
+        }
+        position += 2 + length as usize;
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use image::{Rgb, RgbImage};
+    use turbojpeg::Subsamp;
+
+    use super::*;
+
+    fn compressed() -> Vec<u8> {
+        let image = RgbImage::from_pixel(16, 16, Rgb([200, 100, 50]));
+        turbojpeg::compress_image(&image, 90, Subsamp::None)
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn comment_follows_the_jfif_segment() {
+        let jpeg = compressed();
+        assert_eq!(jpeg[SOI_LENGTH..SOI_LENGTH + 2], APP0);
+        let app0_length = u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+        let position = SOI_LENGTH + 2 + app0_length;
+
+        let comment = "wallpaper-aligner 0,0,1920,1080";
+        let with_comment = insert_comment(&jpeg, comment);
+        assert_eq!(with_comment[..position], jpeg[..position]);
+        assert_eq!(with_comment[position..position + 2], COM);
+        let length = u16::from_be_bytes([with_comment[position + 2], with_comment[position + 3]]);
+        assert_eq!(length as usize, comment.len() + 2);
+        let text = &with_comment[position + 4..position + 4 + comment.len()];
+        assert_eq!(text, comment.as_bytes());
+        assert_eq!(
+            with_comment[position + 4 + comment.len()..],
+            jpeg[position..]
+        );
+    }
+
+    #[test]
+    fn commented_jpeg_still_decodes() {
+        let with_comment = insert_comment(&compressed(), "hello");
+        let image = image::load_from_memory(&with_comment).unwrap();
+        assert_eq!((image.width(), image.height()), (16, 16));
+    }
+
+    /// Inserts an APP1 segment holding `tiff` as EXIF data at `position` of `jpeg`
+    pub fn insert_exif(jpeg: &[u8], position: usize, tiff: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, APP1];
+        segment.extend_from_slice(&((EXIF_HEADER.len() + tiff.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(EXIF_HEADER);
+        segment.extend_from_slice(tiff);
+        [&jpeg[..position], &segment, &jpeg[position..]].concat()
+    }
+
+    #[test]
+    fn exif_is_found_behind_other_segments() {
+        let jpeg = insert_comment(&compressed(), "hello");
+        assert_eq!(exif(&jpeg), None);
+
+        let tiff = crate::exif::tests::tiff_with_date("2024:06:30 18:45:07", false);
+        // Between the JFIF segment and the comment
+        let position = SOI_LENGTH + 2 + u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+        let with_exif = insert_exif(&jpeg, position, &tiff);
+        assert_eq!(exif(&with_exif), Some(tiff.as_slice()));
+        // The start of the file up to the end of the segment is enough
+        let end = with_exif.len() - jpeg.len() + position;
+        assert_eq!(exif(&with_exif[..end]), Some(tiff.as_slice()));
+        assert_eq!(exif(&with_exif[..end - 1]), None);
+    }
+
+    #[test]
+    fn comment_goes_right_after_soi_without_jfif() {
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xDB, 0x00, 0x02, 0xFF, 0xD9];
+        let with_comment = insert_comment(&jpeg, "hi");
+        assert_eq!(
+            with_comment,
+            [0xFF, 0xD8, 0xFF, 0xFE, 0x00, 0x04, b'h', b'i', 0xFF, 0xDB, 0x00, 0x02, 0xFF, 0xD9]
+        );
+    }
+}
@@ -0,0 +1,2143 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use ab_glyph::FontVec;
+use clap::ValueEnum;
+use fast_image_resize::{FilterType, ResizeAlg, ResizeError, ResizeOptions, SrcCropping};
+use hex_color::HexColor;
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::error::{ParameterError, ParameterErrorKind};
+use image::metadata::Orientation;
+use image::{
+    AnimationDecoder, DynamicImage, GenericImage, ImageDecoder, ImageError, ImageFormat,
+    ImageReader, ImageResult, Rgb, RgbImage,
+};
+use imageproc::rect::Rect;
+use pluralizer::pluralize;
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::backend::DisplayBackend;
+use crate::display::{Display, DisplayConfiguration, Rectangle};
+
+pub mod backend;
+mod cache;
+mod colors;
+pub mod display;
+#[cfg(feature = "heic")]
+mod heic;
+pub mod render;
+
+#[derive(ValueEnum, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeMode {
+    /// Fills the entire display with the image. Stretches the image disproportionally as needed
+    Stretch,
+    /// Fills the entire display with the image. Scales the image proportionally and crops the overflow
+    #[value(alias = "fill-crop")]
+    #[serde(alias = "fill-crop")]
+    Fill,
+    /// Fits the entire image into the display. Scales the image proportionally
+    Fit,
+    /// Places the image in the center of the display without scaling. Crops the image if it is larger
+    Center,
+    /// Repeats the image across the display without scaling, starting from its top left corner
+    Tile,
+}
+
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FitBackground {
+    /// Fills the bars with the background color of the image
+    Color,
+    /// Fills the bars with a blurred copy of the image scaled to fill the display
+    Blur,
+    /// Fills the bars by repeating the outermost rows or columns of the image
+    Extend,
+}
+
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResampleFilter {
+    /// Copies the closest pixel. Fastest and keeps hard edges, good for pixel art
+    Nearest,
+    /// Averages the pixels covered by each output pixel. Fast, but blocky when upscaling
+    Box,
+    /// Smooth and fast, but softens downscaled photos
+    Bilinear,
+    /// Sharper than bilinear at about the same speed
+    Hamming,
+    /// Sharp with little ringing
+    CatmullRom,
+    /// Softer than Catmull-Rom with even less ringing
+    Mitchell,
+    /// Sharpest, best for downscaling photos. Can add faint halos around hard edges
+    Lanczos3,
+}
+
+impl ResampleFilter {
+    fn algorithm(self) -> ResizeAlg {
+        let filter = match self {
+            ResampleFilter::Nearest => return ResizeAlg::Nearest,
+            ResampleFilter::Box => FilterType::Box,
+            ResampleFilter::Bilinear => FilterType::Bilinear,
+            ResampleFilter::Hamming => FilterType::Hamming,
+            ResampleFilter::CatmullRom => FilterType::CatmullRom,
+            ResampleFilter::Mitchell => FilterType::Mitchell,
+            ResampleFilter::Lanczos3 => FilterType::Lanczos3,
+        };
+        ResizeAlg::Convolution(filter)
+    }
+}
+
+/// Parses an opaque color given as hex or by its CSS name, the name taking precedence
+pub fn color_parser(value: &str) -> Result<HexColor, String> {
+    colors::named(value)
+        .map_or_else(|| HexColor::parse_rgb(value), Ok)
+        .map_err(|_| "must be a hex color or a CSS color name, e.g. #202020 or navy".to_owned())
+}
+
+/// Parses a clockwise rotation, which has to be a multiple of 90 degrees
+pub fn rotation_parser(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(degrees @ (0 | 90 | 180 | 270)) => Ok(degrees),
+        _ => Err("must be one of 0, 90, 180 or 270".to_owned()),
+    }
+}
+
+/// Parses a region of an image as x,y,width,height in pixels
+pub fn crop_parser(value: &str) -> Result<Crop, String> {
+    let numbers: Vec<_> = value.split(',').map(|n| n.trim().parse::<u32>()).collect();
+    match numbers[..] {
+        [Ok(x), Ok(y), Ok(width), Ok(height)] if width > 0 && height > 0 => Ok(Crop {
+            x,
+            y,
+            width,
+            height,
+        }),
+        _ => Err("must be x,y,width,height in pixels with a width and height above 0".to_owned()),
+    }
+}
+
+/// Region of an image in pixels
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Crop {
+    /// Checks that the region lies within an image of `image_res`
+    fn check(self, filename: &str, image_res: (u32, u32)) -> Result<(), RenderError> {
+        let right = self.x as u64 + self.width as u64;
+        let bottom = self.y as u64 + self.height as u64;
+        if right > image_res.0 as u64 || bottom > image_res.1 as u64 {
+            return Err(RenderError::Crop {
+                filename: filename.to_owned(),
+                crop: self,
+                image_res,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Which ways to mirror an image
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Flip {
+    /// Swap left and right
+    pub horizontal: bool,
+    /// Swap top and bottom
+    pub vertical: bool,
+}
+
+impl Flip {
+    /// Flips the ways either of the two does, so an image can't undo a flip of all images
+    fn or(self, other: Flip) -> Flip {
+        Flip {
+            horizontal: self.horizontal || other.horizontal,
+            vertical: self.vertical || other.vertical,
+        }
+    }
+}
+
+/// Where the data of an image comes from
+#[derive(Debug, Clone)]
+pub enum ImageSource {
+    /// A file that was opened to check it exists. It is opened again by its name for reading,
+    /// as clones would share the position of this handle
+    File(Arc<File>),
+    /// An image read into memory, e.g. from standard input
+    Memory(Arc<[u8]>),
+    /// An http:// or https:// URL the image is downloaded from each time it is read
+    Url(String),
+}
+
+trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+impl ImageSource {
+    /// Returns a reader at the start of the image, so files are read anew
+    /// each time the wallpaper is regenerated. Every reader of `filename` gets its own handle,
+    /// so parallel jobs don't move each other's position. Downloads give up after `timeout` seconds
+    fn rewound(&self, filename: &str, timeout: u64) -> io::Result<Box<dyn ReadSeek>> {
+        match self {
+            ImageSource::File(_) => Ok(Box::new(File::open(filename)?)),
+            ImageSource::Memory(data) => Ok(Box::new(Cursor::new(Arc::clone(data)))),
+            ImageSource::Url(url) => Ok(Box::new(Cursor::new(download(url, timeout)?))),
+        }
+    }
+}
+
+fn download(url: &str, timeout: u64) -> io::Result<Vec<u8>> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(timeout))
+        .build();
+    let response = agent.get(url).call().map_err(io::Error::other)?;
+    let mut data = Vec::new();
+    response.into_reader().read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[derive(Debug, Clone)]
+pub enum WallpaperArgument {
+    Image {
+        source: ImageSource,
+        filename: String,
+        /// Color of the letterbox bars and transparent areas, if it differs from the default
+        background: Option<HexColor>,
+        /// Resize mode overriding the one given with --mode
+        mode: Option<ResizeMode>,
+        /// Clockwise rotation in degrees, applied right after decoding
+        rotation: u32,
+        /// Region of the upright image to use, cut out before it is flipped and rotated
+        crop: Option<Crop>,
+        /// Mirroring applied before the rotation
+        flip: Flip,
+        /// Color blended over the display once the image is drawn, usually translucent
+        tint: Option<HexColor>,
+    },
+    Color(HexColor),
+    Gradient {
+        /// Direction the colors change in, in degrees clockwise from left-to-right,
+        /// so 90 runs from top to bottom
+        angle: f32,
+        stops: Vec<HexColor>,
+    },
+    RadialGradient {
+        /// Where the first color is, as fractions of the display width and height
+        center: (f32, f32),
+        stops: Vec<HexColor>,
+    },
+    /// Leaves the display showing the --background color or --backdrop
+    Skip,
+    /// Stands for the images in the directory until the caller lists them
+    Directory(PathBuf),
+    /// Stands for the files matching the glob pattern until the caller lists them
+    Pattern(String),
+    /// Stands for the image piped to standard input until the caller reads it
+    Stdin,
+}
+
+impl FromStr for WallpaperArgument {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(WallpaperArgument::Skip);
+        }
+        if s == "-" {
+            return Ok(WallpaperArgument::Stdin);
+        }
+        if let Some(rest) = s.strip_prefix("bg=") {
+            let (color, image) = rest
+                .split_once(',')
+                .ok_or("Expected background color and image as bg=<color>,<image>")?;
+            let color = color_parser(color).map_err(|_| "Unable to parse background color")?;
+            let mut argument = WallpaperArgument::from_str(image)?;
+            match &mut argument {
+                WallpaperArgument::Image { background, .. } => *background = Some(color),
+                _ => return Err("Background color can only be set for images"),
+            }
+            return Ok(argument);
+        }
+        if let Some(rest) = s.strip_prefix("rotate=") {
+            let (degrees, image) = rest
+                .split_once(',')
+                .ok_or("Expected rotation and image as rotate=<degrees>,<image>")?;
+            let degrees = rotation_parser(degrees)
+                .map_err(|_| "Expected rotation to be 0, 90, 180 or 270")?;
+            let mut argument = WallpaperArgument::from_str(image)?;
+            match &mut argument {
+                WallpaperArgument::Image { rotation, .. } => *rotation = degrees,
+                _ => return Err("Rotation can only be set for images"),
+            }
+            return Ok(argument);
+        }
+        if let Some(rest) = s.strip_prefix("crop=") {
+            let mut parts = rest.splitn(5, ',');
+            let region: Vec<_> = parts.by_ref().take(4).collect();
+            let image = parts
+                .next()
+                .ok_or("Expected crop and image as crop=<x>,<y>,<width>,<height>,<image>")?;
+            let region = crop_parser(&region.join(","))
+                .map_err(|_| "Expected crop as x,y,width,height with a width and height above 0")?;
+            let mut argument = WallpaperArgument::from_str(image)?;
+            match &mut argument {
+                WallpaperArgument::Image { crop, .. } => *crop = Some(region),
+                _ => return Err("Crop can only be set for images"),
+            }
+            return Ok(argument);
+        }
+        if let Some(rest) = s.strip_prefix("flip=") {
+            let (ways, image) = rest
+                .split_once(',')
+                .ok_or("Expected flip and image as flip=<h|v|hv>,<image>")?;
+            let ways = match ways {
+                "h" => Flip {
+                    horizontal: true,
+                    vertical: false,
+                },
+                "v" => Flip {
+                    horizontal: false,
+                    vertical: true,
+                },
+                "hv" | "vh" => Flip {
+                    horizontal: true,
+                    vertical: true,
+                },
+                _ => return Err("Expected flip to be h, v or hv"),
+            };
+            let mut argument = WallpaperArgument::from_str(image)?;
+            match &mut argument {
+                WallpaperArgument::Image { flip, .. } => *flip = ways,
+                _ => return Err("Flip can only be set for images"),
+            }
+            return Ok(argument);
+        }
+        if let Some(rest) = s.strip_prefix("tint=") {
+            let (color, image) = rest
+                .split_once(',')
+                .ok_or("Expected tint color and image as tint=<color>,<image>")?;
+            let color = HexColor::parse(color).map_err(|_| "Unable to parse tint color")?;
+            let mut argument = WallpaperArgument::from_str(image)?;
+            match &mut argument {
+                WallpaperArgument::Image { tint, .. } => *tint = Some(color),
+                _ => return Err("Tint can only be set for images"),
+            }
+            return Ok(argument);
+        }
+        if let Some(rest) = s.strip_prefix("grad:") {
+            let (angle, stops) = match rest.split_once(':') {
+                Some(("h", stops)) => (0.0, stops),
+                Some(("v", stops)) => (90.0, stops),
+                Some((angle, stops)) => match angle.parse::<f32>() {
+                    Ok(angle) if angle.is_finite() => (angle, stops),
+                    _ => {
+                        return Err("Expected gradient direction to be h, v or an angle in degrees")
+                    }
+                },
+                None => (90.0, rest),
+            };
+            let stops = stops
+                .split('-')
+                .map(HexColor::parse_rgb)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| "Unable to parse gradient colors")?;
+            return match stops[..] {
+                [color] => Ok(WallpaperArgument::Color(color)),
+                _ => Ok(WallpaperArgument::Gradient { angle, stops }),
+            };
+        }
+        if let Some(rest) = s.strip_prefix("radial:") {
+            let (center, stops) = match rest.split_once(':') {
+                Some((center, stops)) => {
+                    let center = center
+                        .split_once(',')
+                        .and_then(|(x, y)| Some((x.parse::<f32>().ok()?, y.parse::<f32>().ok()?)))
+                        .filter(|(x, y)| (0.0..=1.0).contains(x) && (0.0..=1.0).contains(y))
+                        .ok_or("Expected radial gradient center as x,y between 0 and 1")?;
+                    (center, stops)
+                }
+                None => ((0.5, 0.5), rest),
+            };
+            let stops = stops
+                .split('-')
+                .map(HexColor::parse_rgb)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| "Unable to parse gradient colors")?;
+            return match stops[..] {
+                [color] => Ok(WallpaperArgument::Color(color)),
+                _ => Ok(WallpaperArgument::RadialGradient { center, stops }),
+            };
+        }
+        if s.starts_with("http://") || s.starts_with("https://") {
+            let (url, mode) = match s.rsplit_once(':') {
+                Some((url, mode)) => match ResizeMode::from_str(mode, true) {
+                    Ok(mode) => (url, Some(mode)),
+                    Err(_) => (s, None),
+                },
+                None => (s, None),
+            };
+            return Ok(WallpaperArgument::Image {
+                source: ImageSource::Url(url.to_owned()),
+                filename: url.to_owned(),
+                background: None,
+                mode,
+                rotation: 0,
+                crop: None,
+                flip: Flip::default(),
+                tint: None,
+            });
+        }
+        if let Some(color) = colors::named(s) {
+            return Ok(WallpaperArgument::Color(color));
+        }
+        if let Ok(color) = HexColor::parse(s) {
+            return Ok(WallpaperArgument::Color(color));
+        }
+        // Only the last colon can start a mode, so drive letters are left alone
+        if let Some((filename, mode)) = s.rsplit_once(':') {
+            if let Ok(mode) = ResizeMode::from_str(mode, true) {
+                let file = File::open(filename).map_err(|_| "Unable to open file")?;
+                return Ok(WallpaperArgument::Image {
+                    source: ImageSource::File(Arc::new(file)),
+                    filename: filename.to_owned(),
+                    background: None,
+                    mode: Some(mode),
+                    rotation: 0,
+                    crop: None,
+                    flip: Flip::default(),
+                    tint: None,
+                });
+            }
+        }
+        if Path::new(s).is_dir() {
+            return Ok(WallpaperArgument::Directory(PathBuf::from(s)));
+        }
+        // Files that exist are taken literally even if their names contain pattern characters
+        if s.contains(['*', '?', '[']) && !Path::new(s).exists() {
+            return Ok(WallpaperArgument::Pattern(s.to_owned()));
+        }
+        if let Ok(file) = File::open(s) {
+            return Ok(WallpaperArgument::Image {
+                source: ImageSource::File(Arc::new(file)),
+                filename: s.to_owned(),
+                background: None,
+                mode: None,
+                rotation: 0,
+                crop: None,
+                flip: Flip::default(),
+                tint: None,
+            });
+        }
+        Err("Unable to parse color or open file")
+    }
+}
+
+/// Writes the argument the way it is given on the command line, so it parses back to the same one
+impl fmt::Display for WallpaperArgument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |stops: &[HexColor]| {
+            let stops: Vec<_> = stops
+                .iter()
+                .map(|stop| stop.display_rgb().to_string())
+                .collect();
+            stops.join("-")
+        };
+        match self {
+            WallpaperArgument::Image {
+                filename,
+                background,
+                mode,
+                rotation,
+                crop,
+                flip,
+                tint,
+                ..
+            } => {
+                if let Some(background) = background {
+                    write!(f, "bg={},", background.display_rgb())?;
+                }
+                if let Some(tint) = tint {
+                    write!(f, "tint={},", tint.display_rgba())?;
+                }
+                if *rotation != 0 {
+                    write!(f, "rotate={},", rotation)?;
+                }
+                match (flip.horizontal, flip.vertical) {
+                    (true, true) => write!(f, "flip=hv,")?,
+                    (true, false) => write!(f, "flip=h,")?,
+                    (false, true) => write!(f, "flip=v,")?,
+                    (false, false) => {}
+                }
+                if let Some(crop) = crop {
+                    write!(
+                        f,
+                        "crop={},{},{},{},",
+                        crop.x, crop.y, crop.width, crop.height
+                    )?;
+                }
+                write!(f, "{}", filename)?;
+                if let Some(mode) = mode.and_then(|mode| mode.to_possible_value()) {
+                    write!(f, ":{}", mode.get_name())?;
+                }
+                Ok(())
+            }
+            WallpaperArgument::Color(color) if color.a < u8::MAX => {
+                write!(f, "{}", color.display_rgba())
+            }
+            WallpaperArgument::Color(color) => write!(f, "{}", color.display_rgb()),
+            WallpaperArgument::Gradient { angle, stops } => {
+                write!(f, "grad:{}:{}", angle, join(stops))
+            }
+            WallpaperArgument::RadialGradient { center, stops } => {
+                write!(f, "radial:{},{}:{}", center.0, center.1, join(stops))
+            }
+            WallpaperArgument::Skip => Ok(()),
+            WallpaperArgument::Directory(path) => write!(f, "{}", path.display()),
+            WallpaperArgument::Pattern(pattern) => write!(f, "{}", pattern),
+            WallpaperArgument::Stdin => write!(f, "-"),
+        }
+    }
+}
+
+/// How `generate_wallpaper` treats images and the canvas. Fields work like the command line
+/// options of the same name, and `Default` gives the defaults of the command line
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub mode: ResizeMode,
+    /// Rotate images upright as their EXIF orientation says
+    pub auto_orient: bool,
+    pub brightness: i32,
+    pub contrast: f32,
+    pub grayscale: bool,
+    pub saturation: f32,
+    pub square: bool,
+    pub focus: Option<(f64, f64)>,
+    pub smart_crop: bool,
+    /// Region to use of images that don't set their own
+    pub crop: Option<Crop>,
+    /// Mirroring of all images, on top of their own
+    pub flip: Flip,
+    pub filter: ResampleFilter,
+    /// Scale images by whole factors with nearest-neighbor sampling and center them, which keeps
+    /// pixel art crisp
+    pub pixel_art: bool,
+    /// Reuse resized images of earlier runs kept in the temporary directory
+    pub cache: bool,
+    pub dedupe_sources: bool,
+    pub aspect_pad: bool,
+    pub decode_timeout: Option<u64>,
+    /// Number of images decoded and resized at the same time
+    pub decode_concurrency: usize,
+    /// Seconds to wait for an image from a URL to download
+    pub timeout: u64,
+    /// Frame of animated images to use, counting from 0
+    pub frame: usize,
+    pub backdrop: Option<PathBuf>,
+    pub backdrop_sigma: f32,
+    pub pad_color: Option<HexColor>,
+    pub background: Option<HexColor>,
+    /// Color of the canvas between displays, instead of the `background` or `backdrop`
+    pub gap_color: Option<HexColor>,
+    pub fit_background: FitBackground,
+    pub fit_blur_sigma: f32,
+    pub label: bool,
+    pub label_color: Option<HexColor>,
+    pub edge_blend: u32,
+    /// Round the levels of gradients with an ordered dither to hide their banding
+    pub dither: bool,
+    pub mirror: Vec<(usize, usize)>,
+    pub deterministic: bool,
+    pub mosaic: Option<usize>,
+    pub mosaic_cols: Option<usize>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            mode: ResizeMode::Stretch,
+            auto_orient: true,
+            brightness: 0,
+            contrast: 0.0,
+            grayscale: false,
+            saturation: 1.0,
+            square: false,
+            focus: None,
+            smart_crop: false,
+            crop: None,
+            flip: Flip::default(),
+            filter: ResampleFilter::Lanczos3,
+            pixel_art: false,
+            cache: true,
+            dedupe_sources: false,
+            aspect_pad: false,
+            decode_timeout: None,
+            decode_concurrency: 2,
+            timeout: 30,
+            frame: 0,
+            backdrop: None,
+            backdrop_sigma: 50.0,
+            pad_color: None,
+            background: None,
+            gap_color: None,
+            fit_background: FitBackground::Color,
+            fit_blur_sigma: 20.0,
+            label: false,
+            label_color: None,
+            edge_blend: 0,
+            dither: false,
+            mirror: Vec::new(),
+            deterministic: false,
+            mosaic: None,
+            mosaic_cols: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RenderError {
+    /// The number of images doesn't match the number of displays
+    ImageCount {
+        displays: usize,
+        images: usize,
+    },
+    /// --mosaic names a display that wasn't detected
+    MosaicDisplay {
+        display: usize,
+        displays: usize,
+    },
+    /// --mirror names a display that wasn't detected
+    MirrorDisplay {
+        source: usize,
+        target: usize,
+        displays: usize,
+    },
+    Read {
+        filename: String,
+        source: io::Error,
+    },
+    Format {
+        filename: String,
+        source: io::Error,
+    },
+    Size {
+        filename: String,
+        source: ImageError,
+    },
+    Decode {
+        filename: String,
+        source: ImageError,
+    },
+    DecodeTimeout {
+        filename: String,
+        seconds: u64,
+    },
+    DecoderCrashed {
+        filename: String,
+    },
+    Resize {
+        filename: String,
+        source: ResizeError,
+    },
+    Copy {
+        filename: String,
+        source: ImageError,
+    },
+    Crop {
+        filename: String,
+        crop: Crop,
+        image_res: (u32, u32),
+    },
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let displays = |count: usize| pluralize("display", count as isize, true);
+        match self {
+            RenderError::ImageCount {
+                displays: count,
+                images,
+            } => write!(
+                f,
+                "Detected {} but got {}",
+                displays(*count),
+                pluralize("image", *images as isize, true)
+            ),
+            RenderError::MosaicDisplay {
+                display,
+                displays: count,
+            } => write!(
+                f,
+                "Cannot tile images onto display {}, only {} detected",
+                display,
+                displays(*count)
+            ),
+            RenderError::MirrorDisplay {
+                source,
+                target,
+                displays: count,
+            } => write!(
+                f,
+                "Cannot mirror display {} to display {}, only {} detected",
+                source,
+                target,
+                displays(*count)
+            ),
+            RenderError::Read { filename, source } => {
+                write!(f, "Unable to read image '{}': {}", filename, source)
+            }
+            RenderError::Format { filename, source } => write!(
+                f,
+                "Unable to detect image format for '{}': {}",
+                filename, source
+            ),
+            RenderError::Size { filename, source } => {
+                write!(f, "Unable to read size of image '{}': {}", filename, source)
+            }
+            RenderError::Decode { filename, source } => {
+                write!(f, "Unable to decode image '{}': {}", filename, source)
+            }
+            RenderError::DecodeTimeout { filename, seconds } => write!(
+                f,
+                "Unable to decode image '{}': timed out after {}",
+                filename,
+                pluralize("second", *seconds as isize, true)
+            ),
+            RenderError::DecoderCrashed { filename } => {
+                write!(f, "Unable to decode image '{}': decoder crashed", filename)
+            }
+            RenderError::Resize { filename, source } => {
+                write!(f, "Unable to resize image '{}': {}", filename, source)
+            }
+            RenderError::Copy { filename, source } => {
+                write!(f, "Unable to copy image '{}': {}", filename, source)
+            }
+            RenderError::Crop {
+                filename,
+                crop,
+                image_res,
+            } => write!(
+                f,
+                "Unable to crop image '{}': {}x{} at {},{} is outside of its {}x{} pixels",
+                filename, crop.width, crop.height, crop.x, crop.y, image_res.0, image_res.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+/// A composed wallpaper along with what ended up on each display
+#[derive(Debug)]
+pub struct Wallpaper {
+    pub image: RgbImage,
+    /// Describes what was drawn onto each display or --mosaic cell, in order
+    pub stats: Vec<String>,
+    /// Images that couldn't be drawn along with the indices of the displays they were meant for,
+    /// which show the background instead. Several displays share an image with `dedupe_sources`
+    pub failures: Vec<(Vec<usize>, RenderError)>,
+    /// Problems that were worked around, e.g. a backdrop that couldn't be loaded
+    pub warnings: Vec<String>,
+    covered_pixels: u64,
+}
+
+impl Wallpaper {
+    /// Share of the canvas in percent that no image or color covers
+    pub fn background_percent(&self) -> f64 {
+        let canvas_pixels = self.image.width() as u64 * self.image.height() as u64;
+        percent(
+            canvas_pixels.saturating_sub(self.covered_pixels),
+            canvas_pixels,
+        )
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 * 100.0 / total as f64
+}
+
+fn to_rgb(color: HexColor) -> Rgb<u8> {
+    Rgb([color.r, color.g, color.b])
+}
+
+/// Lists the displays of the system backend, sorted top-to-bottom, then left-to-right.
+/// On Windows bounds are in physical pixels only if the process is aware of per-display DPI,
+/// otherwise Windows scales them. With `debug` it prints how display names are resolved
+pub fn detect_displays(debug: bool) -> io::Result<DisplayConfiguration> {
+    detect_displays_with(backend::system(debug).as_ref())
+}
+
+/// Lists the displays of `backend`, sorted like `detect_displays` does
+pub fn detect_displays_with(backend: &dyn DisplayBackend) -> io::Result<DisplayConfiguration> {
+    let mut config = backend.detect()?;
+    config.sort_by_position();
+    Ok(config)
+}
+
+/// Checks that `images` fit the displays of `config` and the display numbers in `options` exist
+pub fn validate(
+    config: &DisplayConfiguration,
+    images: &[WallpaperArgument],
+    options: &RenderOptions,
+) -> Result<(), RenderError> {
+    let displays = config.displays.len();
+    if let Some(display) = options.mosaic.filter(|display| *display > displays) {
+        return Err(RenderError::MosaicDisplay { display, displays });
+    }
+    if options.mosaic.is_none() && displays != images.len() {
+        return Err(RenderError::ImageCount {
+            displays,
+            images: images.len(),
+        });
+    }
+    if let Some(&(source, target)) = options
+        .mirror
+        .iter()
+        .find(|(source, target)| *source.max(target) > displays)
+    {
+        return Err(RenderError::MirrorDisplay {
+            source,
+            target,
+            displays,
+        });
+    }
+    Ok(())
+}
+
+/// Composes a wallpaper from one image or color per display of `config`, or per --mosaic cell.
+/// Displays are drawn where they are after `normalize`, so scale `config` first to render
+/// at another size. Directories, patterns and stdin have to be turned into images beforehand.
+/// Images that can't be drawn are listed in `failures` rather than failing the whole wallpaper
+pub fn generate_wallpaper(
+    config: &DisplayConfiguration,
+    images: &[WallpaperArgument],
+    options: &RenderOptions,
+) -> Result<Wallpaper, RenderError> {
+    generate_wallpaper_with_progress(config, images, options, &|_| {})
+}
+
+/// Same as `generate_wallpaper`, but calls `progress` with the image or color of each display
+/// once it is ready. Images are prepared in parallel, so `progress` is called from several threads
+pub fn generate_wallpaper_with_progress(
+    config: &DisplayConfiguration,
+    images: &[WallpaperArgument],
+    options: &RenderOptions,
+    progress: &(dyn Fn(&str) + Sync),
+) -> Result<Wallpaper, RenderError> {
+    validate(config, images, options)?;
+    let config = config.normalized();
+    let virtual_resolution = config.bounds.resolution();
+    let mut warnings = Vec::new();
+    let backdrop = match &options.backdrop {
+        Some(path) => match load_backdrop(path, virtual_resolution, options) {
+            Ok(backdrop) => Some(backdrop),
+            Err(warning) => {
+                warnings.push(warning);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut output = backdrop.unwrap_or_else(|| {
+        RgbImage::from_pixel(
+            virtual_resolution.0,
+            virtual_resolution.1,
+            to_rgb(options.background.unwrap_or(HexColor::BLACK)),
+        )
+    });
+    if let Some(color) = options.gap_color {
+        for gap in config.gaps() {
+            let (width, height) = gap.resolution();
+            imageproc::drawing::draw_filled_rect_mut(
+                &mut output,
+                Rect::at(gap.min_x, gap.min_y).of_size(width, height),
+                to_rgb(color),
+            );
+        }
+    }
+
+    let targets = render_targets(&config, images.len(), options);
+    // Decoding and resizing take the most time and images don't depend on each other,
+    // so all of them are prepared in parallel before anything is drawn
+    let mut jobs = Vec::new();
+    let mut image_jobs = vec![None; images.len()];
+    for (idx, arg) in images.iter().enumerate() {
+        if let WallpaperArgument::Image {
+            filename,
+            background,
+            mode,
+            rotation,
+            crop,
+            flip,
+            ..
+        } = arg
+        {
+            let key = (
+                std::fs::canonicalize(filename).unwrap_or_else(|_| filename.into()),
+                ImageJob {
+                    display_res: targets[idx].bounds.resolution(),
+                    // Transparent areas blend into the color the display shows around the image
+                    background: background
+                        .or(options.pad_color)
+                        .or(options.background)
+                        .unwrap_or(HexColor::BLACK),
+                    mode: mode.unwrap_or(options.mode),
+                    rotation: *rotation,
+                    crop: crop.or(options.crop),
+                    flip: flip.or(options.flip),
+                },
+            );
+            let existing = if options.dedupe_sources {
+                jobs.iter().position(|(_, job_key)| *job_key == key)
+            } else {
+                None
+            };
+            image_jobs[idx] = Some(existing.unwrap_or_else(|| {
+                jobs.push((idx, key));
+                jobs.len() - 1
+            }));
+        }
+    }
+    // Every running job holds a full decoded source image, so only `decode_concurrency` of them
+    // run at once to keep the memory use in check with many large images
+    let batch_size = options.decode_concurrency.max(1);
+    let mut resized: Vec<Result<Resized, RenderError>> = Vec::with_capacity(jobs.len());
+    for (batch, batch_jobs) in jobs.chunks(batch_size).enumerate() {
+        resized.par_extend(
+            batch_jobs
+                .par_iter()
+                .enumerate()
+                .map(|(i, (idx, (_, image_job)))| {
+                    let job = batch * batch_size + i;
+                    let WallpaperArgument::Image {
+                        source, filename, ..
+                    } = &images[*idx]
+                    else {
+                        unreachable!("jobs are only created for images");
+                    };
+                    let resized = cached_resize_image(source, filename, image_job, options);
+                    // Deduplicated images are ready for every display that shows them at once
+                    let displays = image_jobs
+                        .iter()
+                        .filter(|image_job| **image_job == Some(job));
+                    for _ in displays {
+                        progress(filename);
+                    }
+                    resized
+                }),
+        );
+    }
+
+    let mut stats = vec!["not rendered".to_owned(); images.len()];
+    let mut failures = Vec::new();
+    let mut covered_pixels = 0u64;
+    for (idx, arg) in images.iter().enumerate() {
+        let display = targets.get(idx).expect("there is a target for every image");
+        let display_res = display.bounds.resolution();
+        if !matches!(arg, WallpaperArgument::Image { .. }) {
+            progress(&arg.to_string());
+        }
+        match arg {
+            WallpaperArgument::Image {
+                filename,
+                background,
+                tint,
+                ..
+            } => {
+                let job = image_jobs[idx].expect("every image has a job");
+                let Ok(rgb8) = &resized[job] else {
+                    continue;
+                };
+                let dest_res = rgb8.image.dimensions();
+                // Otherwise the canvas already shows --background or the backdrop around the image
+                let fill = background.or(options.pad_color);
+                if let Some(fill) = fill.filter(|_| dest_res != display_res) {
+                    imageproc::drawing::draw_filled_rect_mut(
+                        &mut output,
+                        Rect::at(display.bounds.min_x, display.bounds.min_y)
+                            .of_size(display_res.0, display_res.1),
+                        to_rgb(fill),
+                    );
+                }
+                let tile = display.bounds.centered(dest_res);
+                // Only copy the part of the image that fits onto the canvas
+                let Some(visible) = tile.intersection(&config.bounds) else {
+                    continue;
+                };
+                let (visible_width, visible_height) = visible.resolution();
+                let part = image::imageops::crop_imm(
+                    &rgb8.image,
+                    (visible.min_x - tile.min_x) as u32,
+                    (visible.min_y - tile.min_y) as u32,
+                    visible_width,
+                    visible_height,
+                );
+                if let Err(source) =
+                    output.copy_from(&*part, visible.min_x as u32, visible.min_y as u32)
+                {
+                    let filename = filename.clone();
+                    failures.push((vec![idx], RenderError::Copy { filename, source }));
+                    continue;
+                }
+                if let Some(tint) = tint {
+                    render::blend_fill(&mut output, &display.bounds, to_rgb(*tint), tint.a);
+                }
+
+                let display_pixels = display.bounds.area();
+                let image_pixels = visible.area();
+                covered_pixels += image_pixels;
+                stats[idx] = format!(
+                    "{} of {} source pixels used ({:.1}% cropped), {:.1}% letterbox",
+                    rgb8.used_pixels,
+                    rgb8.source_pixels,
+                    percent(rgb8.source_pixels - rgb8.used_pixels, rgb8.source_pixels),
+                    percent(display_pixels.saturating_sub(image_pixels), display_pixels)
+                );
+            }
+            WallpaperArgument::Color(color) => {
+                if color.a < u8::MAX {
+                    stats[idx] = format!("color {}", color.display_rgba());
+                    covered_pixels += display.bounds.area();
+                    render::blend_fill(&mut output, &display.bounds, to_rgb(*color), color.a);
+                    continue;
+                }
+                stats[idx] = format!("color {}", color.display_rgb());
+                covered_pixels += display.bounds.area();
+                imageproc::drawing::draw_filled_rect_mut(
+                    &mut output,
+                    Rect::at(display.bounds.min_x, display.bounds.min_y)
+                        .of_size(display_res.0, display_res.1),
+                    to_rgb(*color),
+                );
+            }
+            WallpaperArgument::Gradient { angle, stops } => {
+                let stops: Vec<_> = stops.iter().map(|stop| to_rgb(*stop)).collect();
+                stats[idx] = format!(
+                    "gradient of {}",
+                    pluralize("color", stops.len() as isize, true)
+                );
+                covered_pixels += display.bounds.area();
+                render::fill_gradient(&mut output, &display.bounds, &stops, *angle, options.dither);
+            }
+            WallpaperArgument::RadialGradient { center, stops } => {
+                let stops: Vec<_> = stops.iter().map(|stop| to_rgb(*stop)).collect();
+                stats[idx] = format!(
+                    "radial gradient of {}",
+                    pluralize("color", stops.len() as isize, true)
+                );
+                covered_pixels += display.bounds.area();
+                render::fill_radial_gradient(
+                    &mut output,
+                    &display.bounds,
+                    &stops,
+                    *center,
+                    options.dither,
+                );
+            }
+            WallpaperArgument::Skip => stats[idx] = "skipped".to_owned(),
+            WallpaperArgument::Directory(_)
+            | WallpaperArgument::Pattern(_)
+            | WallpaperArgument::Stdin => {
+                unreachable!("directories, patterns and stdin are expanded before rendering")
+            }
+        }
+    }
+    for (job, result) in resized.into_iter().enumerate() {
+        match result {
+            Ok(resized) => warnings.extend(resized.warnings),
+            Err(err) => {
+                let displays = (0..images.len())
+                    .filter(|idx| image_jobs[*idx] == Some(job))
+                    .collect();
+                failures.push((displays, err));
+            }
+        }
+    }
+    failures.sort_by_key(|(displays, _)| displays[0]);
+
+    let mut mirror_resizer = render::resizer(options.deterministic);
+    for &(source, target) in &options.mirror {
+        let source = &config.displays[source - 1].bounds;
+        let target = &config.displays[target - 1].bounds;
+        if let Err(err) = render::mirror_region(&mut mirror_resizer, &mut output, source, target) {
+            warnings.push(format!("Unable to mirror display: {}", err));
+        }
+    }
+    if options.edge_blend > 0 {
+        for display in &config.displays {
+            for other in &config.displays {
+                if let Some((edge, start, end)) = display.bounds.shared_edge(&other.bounds) {
+                    render::darken_edge(
+                        &mut output,
+                        &display.bounds,
+                        edge,
+                        start,
+                        end,
+                        options.edge_blend,
+                    );
+                }
+            }
+        }
+    }
+    if options.label {
+        match load_label_font() {
+            Ok(font) => {
+                for display in &config.displays {
+                    let (width, height) = display.bounds.resolution();
+                    let text = format!("{} ({}x{})", display.name, width, height);
+                    let color = options.label_color.map(to_rgb);
+                    render::draw_label(&mut output, &display.bounds, &text, color, &font);
+                }
+            }
+            Err(err) => warnings.push(format!("Unable to load label font: {}", err)),
+        }
+    }
+    Ok(Wallpaper {
+        image: output,
+        stats,
+        failures,
+        warnings,
+        covered_pixels,
+    })
+}
+
+/// Regions of the canvas the images go to, in order
+pub fn render_targets(
+    config: &DisplayConfiguration,
+    count: usize,
+    options: &RenderOptions,
+) -> Vec<Display> {
+    match options.mosaic {
+        Some(display) => {
+            let display = &config.displays[display - 1];
+            display
+                .bounds
+                .grid(count, options.mosaic_cols)
+                .into_iter()
+                .enumerate()
+                .map(|(i, bounds)| Display {
+                    name: format!("{}, cell {}", display.name, i + 1),
+                    bounds,
+                    dpi: display.dpi,
+                    primary: display.primary,
+                })
+                .collect()
+        }
+        None => config.displays.clone(),
+    }
+}
+
+/// Works out the size `generate_wallpaper` would give an image from its header alone
+pub fn planned_resolution(
+    source: &ImageSource,
+    filename: &str,
+    display_res: (u32, u32),
+    mode: ResizeMode,
+    rotation: u32,
+    crop: Option<Crop>,
+    options: &RenderOptions,
+) -> Result<(u32, u32), RenderError> {
+    let dimensions = source
+        .rewound(filename, options.timeout)
+        .map_err(ImageError::IoError)
+        .and_then(|reader| {
+            ImageReader::new(BufReader::new(reader))
+                .with_guessed_format()
+                .map_err(ImageError::IoError)
+        })
+        .and_then(|reader| read_dimensions(reader, options.auto_orient));
+    let mut image_res = dimensions.map_err(|source| RenderError::Size {
+        filename: filename.to_owned(),
+        source,
+    })?;
+    if let Some(crop) = crop {
+        crop.check(filename, image_res)?;
+        image_res = (crop.width, crop.height);
+    }
+    if rotation % 180 == 90 {
+        image_res = (image_res.1, image_res.0);
+    }
+    if options.square {
+        let side = image_res.0.min(image_res.1);
+        image_res = (side, side);
+    }
+    if options.pixel_art && mode != ResizeMode::Tile {
+        let factor = integer_scale(image_res, display_res);
+        return Ok((
+            (image_res.0 * factor).min(display_res.0),
+            (image_res.1 * factor).min(display_res.1),
+        ));
+    }
+    Ok(match mode {
+        ResizeMode::Stretch | ResizeMode::Fill | ResizeMode::Tile => display_res,
+        ResizeMode::Fit if options.fit_background != FitBackground::Color => display_res,
+        ResizeMode::Fit => fit_resolution(image_res, display_res),
+        ResizeMode::Center => (
+            image_res.0.min(display_res.0),
+            image_res.1.min(display_res.1),
+        ),
+    })
+}
+
+/// Scales `image_res` proportionally so it touches the edges of `display_res` from the inside
+fn fit_resolution(image_res: (u32, u32), display_res: (u32, u32)) -> (u32, u32) {
+    // Integer math, since rounding float ratios could make a side a pixel longer than the display
+    // or pick the wrong axis for images that have almost the aspect ratio of the display
+    let (image_width, image_height) = (image_res.0 as u64, image_res.1 as u64);
+    let (display_width, display_height) = (display_res.0 as u64, display_res.1 as u64);
+    if image_width * display_height > image_height * display_width {
+        let height = (2 * image_height * display_width + image_width) / (2 * image_width);
+        (display_res.0, height.clamp(1, display_height) as u32)
+    } else {
+        let width = (2 * image_width * display_height + image_height) / (2 * image_height);
+        (width.clamp(1, display_width) as u32, display_res.1)
+    }
+}
+
+/// Largest whole factor `image_res` can be scaled by to fit into `display_res`, but at least 1
+fn integer_scale(image_res: (u32, u32), display_res: (u32, u32)) -> u32 {
+    (display_res.0 / image_res.0)
+        .min(display_res.1 / image_res.1)
+        .max(1)
+}
+
+/// Loads Segoe UI, which every Windows installation has
+fn load_label_font() -> Result<FontVec, String> {
+    let windows_dir = std::env::var_os("WINDIR").unwrap_or_else(|| OsString::from(r"C:\Windows"));
+    let path = Path::new(&windows_dir).join("Fonts").join("segoeui.ttf");
+    let data = std::fs::read(&path).map_err(|err| format!("'{}': {}", path.display(), err))?;
+    FontVec::try_from_vec(data).map_err(|err| format!("'{}': {}", path.display(), err))
+}
+
+/// Loads the --backdrop, or describes why it couldn't be loaded
+fn load_backdrop(
+    path: &Path,
+    size: (u32, u32),
+    options: &RenderOptions,
+) -> Result<RgbImage, String> {
+    let reader = ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|err| format!("Unable to open backdrop '{}': {}", path.display(), err))?;
+    let (image, _) = decode(reader, options.auto_orient, 0)
+        .map_err(|err| format!("Unable to decode backdrop '{}': {}", path.display(), err))?;
+    let image = render::flatten(image, Rgb([0, 0, 0]));
+    let mut resizer = render::resizer(options.deterministic);
+    render::blurred_backdrop(&mut resizer, image, size, options.backdrop_sigma)
+        .map_err(|err| format!("Unable to resize backdrop '{}': {}", path.display(), err))
+}
+
+/// Reads the EXIF orientation of an image, treating a missing or unreadable tag as upright
+fn orientation(decoder: &mut impl ImageDecoder, auto_orient: bool) -> Orientation {
+    if !auto_orient {
+        return Orientation::NoTransforms;
+    }
+    decoder.orientation().unwrap_or(Orientation::NoTransforms)
+}
+
+/// Reads the whole image if it is a HEIF file, which the image crate can't decode,
+/// or hands the reader back otherwise
+#[cfg(feature = "heic")]
+fn read_heif<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+) -> ImageResult<Result<Vec<u8>, ImageReader<R>>> {
+    if reader.format().is_some() {
+        return Ok(Err(reader));
+    }
+    let mut inner = reader.into_inner();
+    if !heic::is_heif(inner.fill_buf()?) {
+        return Ok(Err(ImageReader::new(inner)));
+    }
+    let mut data = Vec::new();
+    inner.read_to_end(&mut data)?;
+    Ok(Ok(data))
+}
+
+/// Reads the size of an image from its header, swapping the sides if it is turned upright
+fn read_dimensions<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    auto_orient: bool,
+) -> ImageResult<(u32, u32)> {
+    #[cfg(feature = "heic")]
+    let reader = match read_heif(reader)? {
+        Ok(data) => return heic::dimensions(&data),
+        Err(reader) => reader,
+    };
+    let mut decoder = reader.into_decoder()?;
+    let dimensions = decoder.dimensions();
+    Ok(match orientation(&mut decoder, auto_orient) {
+        Orientation::Rotate90
+        | Orientation::Rotate270
+        | Orientation::Rotate90FlipH
+        | Orientation::Rotate270FlipH => (dimensions.1, dimensions.0),
+        _ => dimensions,
+    })
+}
+
+/// Decodes an image and turns it upright unless `auto_orient` is off. Animated images are reduced
+/// to frame `frame`, counting from 0, and reported as animated next to the image
+fn decode<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    auto_orient: bool,
+    frame: usize,
+) -> ImageResult<(DynamicImage, bool)> {
+    #[cfg(feature = "heic")]
+    let reader = match read_heif(reader)? {
+        Ok(data) => return Ok((heic::decode(&data)?, false)),
+        Err(reader) => reader,
+    };
+    let frames = match reader.format() {
+        Some(ImageFormat::Gif) => GifDecoder::new(reader.into_inner())?.into_frames(),
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(reader.into_inner())?;
+            if !decoder.is_apng()? {
+                return Ok((upright(decoder, auto_orient)?, false));
+            }
+            decoder.apng()?.into_frames()
+        }
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(reader.into_inner())?;
+            if !decoder.has_animation() {
+                return Ok((upright(decoder, auto_orient)?, false));
+            }
+            decoder.into_frames()
+        }
+        _ => return Ok((upright(reader.into_decoder()?, auto_orient)?, false)),
+    };
+    // One frame past the selected one is enough to tell whether the image is animated
+    let mut count = 0;
+    let mut selected = None;
+    for result in frames.take(frame + 2) {
+        if count == frame {
+            selected = Some(result?);
+        }
+        count += 1;
+    }
+    let Some(selected) = selected else {
+        return Err(ImageError::Parameter(ParameterError::from_kind(
+            ParameterErrorKind::Generic(format!(
+                "image has only {}",
+                pluralize("frame", count as isize, true)
+            )),
+        )));
+    };
+    Ok((DynamicImage::ImageRgba8(selected.into_buffer()), count > 1))
+}
+
+/// Decodes a still image and turns it upright unless `auto_orient` is off
+fn upright(mut decoder: impl ImageDecoder, auto_orient: bool) -> ImageResult<DynamicImage> {
+    let orientation = orientation(&mut decoder, auto_orient);
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// How much --mode stretch may squash an image before it is worth a warning
+const MAX_DISTORTION: f32 = 1.1;
+
+/// How one image is prepared for its display, on top of the `RenderOptions`.
+/// Displays showing the same file with equal jobs can share the result with `dedupe_sources`
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ImageJob {
+    display_res: (u32, u32),
+    background: HexColor,
+    mode: ResizeMode,
+    rotation: u32,
+    crop: Option<Crop>,
+    flip: Flip,
+}
+
+/// Lists everything the result of `resize_image` depends on
+fn cache_key(
+    source: &ImageSource,
+    filename: &str,
+    job: &ImageJob,
+    options: &RenderOptions,
+) -> Option<String> {
+    // Images in memory have no modification time to tell whether they changed
+    let ImageSource::File(file) = source else {
+        return None;
+    };
+    let metadata = file.metadata().ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    let identity = (
+        env!("CARGO_PKG_VERSION"),
+        std::fs::canonicalize(filename).ok()?,
+        modified.as_nanos(),
+        metadata.len(),
+    );
+    // Grouped because Debug is only implemented for tuples of up to 12 elements
+    let settings = (
+        (
+            options.square,
+            options.aspect_pad,
+            options.smart_crop,
+            options.focus,
+            options.filter,
+            options.pixel_art,
+            options.frame,
+        ),
+        (
+            options.fit_background,
+            options.fit_blur_sigma,
+            options.auto_orient,
+            options.deterministic,
+        ),
+        (
+            options.brightness,
+            options.contrast,
+            options.grayscale,
+            options.saturation,
+        ),
+    );
+    Some(format!("{:?}", (identity, job, settings)))
+}
+
+/// Same as `resize_image`, but reuses the result of an earlier run with the same inputs
+fn cached_resize_image(
+    source: &ImageSource,
+    filename: &str,
+    job: &ImageJob,
+    options: &RenderOptions,
+) -> Result<Resized, RenderError> {
+    let key = if options.cache {
+        cache_key(source, filename, job, options)
+    } else {
+        None
+    };
+    if let Some(resized) = key.as_deref().and_then(cache::load) {
+        return Ok(resized);
+    }
+    let mut resized = resize_image(source, filename, job, options)?;
+    if let Some(key) = &key {
+        if let Err(err) = cache::store(key, &resized) {
+            resized
+                .warnings
+                .push(format!("Unable to cache image '{}': {}", filename, err));
+        }
+    }
+    Ok(resized)
+}
+
+struct Resized {
+    image: RgbImage,
+    /// Pixels of the decoded source image
+    source_pixels: u64,
+    /// Source pixels that survived cropping
+    used_pixels: u64,
+    /// Problems that were worked around while preparing the image
+    warnings: Vec<String>,
+}
+
+fn resize_image(
+    source: &ImageSource,
+    filename: &str,
+    job: &ImageJob,
+    options: &RenderOptions,
+) -> Result<Resized, RenderError> {
+    let ImageJob {
+        display_res,
+        background,
+        mode,
+        rotation,
+        crop,
+        flip,
+    } = *job;
+    let reader = source
+        .rewound(filename, options.timeout)
+        .map_err(|source| RenderError::Read {
+            filename: filename.to_owned(),
+            source,
+        })?;
+    let reader = ImageReader::new(BufReader::new(reader))
+        .with_guessed_format()
+        .map_err(|source| RenderError::Format {
+            filename: filename.to_owned(),
+            source,
+        })?;
+    let auto_orient = options.auto_orient;
+    let frame = options.frame;
+    let decoded = match options.decode_timeout {
+        Some(seconds) => {
+            // The decoder can't be interrupted, so on timeout the thread is left to finish on its own
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                let _ = sender.send(decode(reader, auto_orient, frame));
+            });
+            match receiver.recv_timeout(Duration::from_secs(seconds)) {
+                Ok(decoded) => decoded,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(RenderError::DecodeTimeout {
+                        filename: filename.to_owned(),
+                        seconds,
+                    });
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(RenderError::DecoderCrashed {
+                        filename: filename.to_owned(),
+                    });
+                }
+            }
+        }
+        None => decode(reader, auto_orient, frame),
+    };
+    let (image, animated) = decoded.map_err(|source| RenderError::Decode {
+        filename: filename.to_owned(),
+        source,
+    })?;
+    let mut warnings = Vec::new();
+    if animated {
+        warnings.push(format!(
+            "Image '{}' is animated, only frame {} is used",
+            filename,
+            frame + 1
+        ));
+    }
+    let source_pixels = image.width() as u64 * image.height() as u64;
+    let image = match crop {
+        Some(crop) => {
+            crop.check(filename, (image.width(), image.height()))?;
+            image.crop_imm(crop.x, crop.y, crop.width, crop.height)
+        }
+        None => image,
+    };
+    let background = to_rgb(background);
+    // Flipping first keeps flip=h swapping the sides of the upright image, whatever the rotation
+    let mut image = render::flatten(image, background);
+    if flip.horizontal {
+        image::imageops::flip_horizontal_in_place(&mut image);
+    }
+    if flip.vertical {
+        image::imageops::flip_vertical_in_place(&mut image);
+    }
+    let mut image = render::rotate(image, rotation);
+    // Adjusting before resizing means every pixel is resampled only once
+    if options.brightness != 0 {
+        image = image::imageops::brighten(&image, options.brightness);
+    }
+    if options.contrast != 0.0 {
+        image = image::imageops::contrast(&image, options.contrast);
+    }
+    if options.grayscale {
+        image = DynamicImage::ImageLuma8(image::imageops::grayscale(&image)).into_rgb8();
+    } else if options.saturation != 1.0 {
+        render::saturate(&mut image, options.saturation);
+    }
+    if options.square {
+        image = render::crop_square(&image);
+    }
+    let mut used_pixels = image.width() as u64 * image.height() as u64;
+    let pixel_art = options.pixel_art && mode != ResizeMode::Tile;
+    if options.aspect_pad && matches!(mode, ResizeMode::Stretch) && !pixel_art {
+        image = render::pad_to_aspect(&image, display_res, background);
+    }
+    if mode == ResizeMode::Stretch && !pixel_art {
+        let image_ratio = image.width() as f32 / image.height() as f32;
+        let display_ratio = Rectangle::from_xywh(0, 0, display_res.0, display_res.1).aspect_ratio();
+        let distortion = (image_ratio / display_ratio).max(display_ratio / image_ratio);
+        if distortion > MAX_DISTORTION {
+            warnings.push(format!(
+                "Image '{}' is distorted by {:.0}%, fit or fill would keep its proportions",
+                filename,
+                (distortion - 1.0) * 100.0
+            ));
+        }
+    }
+
+    // Every source pixel becomes a square of whole pixels, the rest works like --mode center
+    let mut factor = 1;
+    if pixel_art {
+        factor = integer_scale(image.dimensions(), display_res);
+        image = image::imageops::resize(
+            &image,
+            image.width() * factor,
+            image.height() * factor,
+            image::imageops::FilterType::Nearest,
+        );
+    }
+
+    if mode == ResizeMode::Center || pixel_art {
+        let width = image.width().min(display_res.0);
+        let height = image.height().min(display_res.1);
+        let image = image::imageops::crop_imm(
+            &image,
+            (image.width() - width) / 2,
+            (image.height() - height) / 2,
+            width,
+            height,
+        )
+        .to_image();
+        return Ok(Resized {
+            image,
+            source_pixels,
+            used_pixels: (width / factor) as u64 * (height / factor) as u64,
+            warnings,
+        });
+    }
+
+    if let ResizeMode::Tile = mode {
+        used_pixels =
+            image.width().min(display_res.0) as u64 * image.height().min(display_res.1) as u64;
+        return Ok(Resized {
+            image: render::tile(&image, display_res),
+            source_pixels,
+            used_pixels,
+            warnings,
+        });
+    }
+
+    let mut resizer = render::resizer(options.deterministic);
+    let cropping = match mode {
+        ResizeMode::Stretch => SrcCropping::None,
+        ResizeMode::Fill if options.smart_crop => {
+            SrcCropping::FitIntoDestination(render::smart_crop_centering(&image, display_res))
+        }
+        ResizeMode::Fill => SrcCropping::FitIntoDestination(options.focus.unwrap_or((0.5, 0.5))),
+        ResizeMode::Fit => SrcCropping::None,
+        ResizeMode::Center | ResizeMode::Tile => {
+            unreachable!("images are not resized in this mode")
+        }
+    };
+    if let SrcCropping::FitIntoDestination(_) = cropping {
+        let image_ratio = image.width() as f64 / image.height() as f64;
+        let display_ratio = display_res.0 as f64 / display_res.1 as f64;
+        let kept = (image_ratio / display_ratio).min(display_ratio / image_ratio);
+        used_pixels = (used_pixels as f64 * kept).round() as u64;
+    }
+    let image = DynamicImage::ImageRgb8(image);
+    let dest_res = match mode {
+        ResizeMode::Stretch | ResizeMode::Fill => (display_res.0, display_res.1),
+        ResizeMode::Fit => fit_resolution((image.width(), image.height()), display_res),
+        ResizeMode::Center | ResizeMode::Tile => {
+            unreachable!("images are not resized in this mode")
+        }
+    };
+    let mut destination = DynamicImage::ImageRgb8(RgbImage::new(dest_res.0, dest_res.1));
+    resizer
+        .resize(
+            &image,
+            &mut destination,
+            &ResizeOptions {
+                algorithm: options.filter.algorithm(),
+                cropping,
+                ..Default::default()
+            },
+        )
+        .map_err(|source| RenderError::Resize {
+            filename: filename.to_owned(),
+            source,
+        })?;
+
+    let mut resized = destination.into_rgb8();
+    if mode == ResizeMode::Fit
+        && options.fit_background == FitBackground::Blur
+        && dest_res != display_res
+    {
+        match render::blurred_backdrop(
+            &mut resizer,
+            image.into_rgb8(),
+            display_res,
+            options.fit_blur_sigma,
+        ) {
+            Ok(mut backdrop) => {
+                image::imageops::replace(
+                    &mut backdrop,
+                    &resized,
+                    ((display_res.0 - dest_res.0) / 2) as i64,
+                    ((display_res.1 - dest_res.1) / 2) as i64,
+                );
+                resized = backdrop;
+            }
+            Err(err) => warnings.push(format!(
+                "Unable to blur background of image '{}': {}",
+                filename, err
+            )),
+        }
+    }
+    if mode == ResizeMode::Fit
+        && options.fit_background == FitBackground::Extend
+        && dest_res != display_res
+    {
+        resized = render::extend_edges(&resized, display_res);
+    }
+
+    Ok(Resized {
+        image: resized,
+        source_pixels,
+        used_pixels,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    fn display(name: &str, x: i32, y: i32, width: u32, height: u32) -> Display {
+        Display {
+            name: name.to_owned(),
+            bounds: Rectangle::from_xywh(x, y, width, height),
+            dpi: 96,
+            primary: x == 0 && y == 0,
+        }
+    }
+
+    /// Wraps `image` into a PNG in memory, like an image piped to standard input
+    fn png_argument(image: &RgbaImage) -> WallpaperArgument {
+        let mut data = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        WallpaperArgument::Image {
+            source: ImageSource::Memory(data.into()),
+            filename: "<stdin>".to_owned(),
+            background: None,
+            mode: None,
+            rotation: 0,
+            crop: None,
+            flip: Flip::default(),
+            tint: None,
+        }
+    }
+
+    /// Opens the image at `path` like one given on the command line
+    fn file_argument(path: &Path) -> WallpaperArgument {
+        WallpaperArgument::Image {
+            source: ImageSource::File(Arc::new(File::open(path).unwrap())),
+            filename: path.to_string_lossy().into_owned(),
+            background: None,
+            mode: None,
+            rotation: 0,
+            crop: None,
+            flip: Flip::default(),
+            tint: None,
+        }
+    }
+
+    /// An opaque image whose colors change in both directions, so resampling has work to do
+    fn pattern(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([
+                (x * 7 % 256) as u8,
+                (y * 13 % 256) as u8,
+                ((x ^ y) % 256) as u8,
+                255,
+            ])
+        })
+    }
+
+    #[test]
+    fn readers_of_a_file_keep_their_own_position() {
+        let path = std::env::temp_dir().join(format!(
+            "wallpaper-aligner-test-{}-readers.png",
+            std::process::id()
+        ));
+        pattern(40, 30).save(&path).unwrap();
+        let filename = path.to_string_lossy().into_owned();
+        let source = ImageSource::File(Arc::new(File::open(&path).unwrap()));
+        let mut first = source.rewound(&filename, 30).unwrap();
+        let mut second = source.rewound(&filename, 30).unwrap();
+        let mut signature = [0; 8];
+        first.read_exact(&mut signature).unwrap();
+        second.read_exact(&mut signature).unwrap();
+        assert_eq!(&signature, b"\x89PNG\r\n\x1a\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parallel_jobs_read_a_shared_file_independently() {
+        let path = std::env::temp_dir().join(format!(
+            "wallpaper-aligner-test-{}-parallel.png",
+            std::process::id()
+        ));
+        pattern(400, 300).save(&path).unwrap();
+        let argument = file_argument(&path);
+        // Each display gets its own job, all of them reading the same file at once
+        let displays = [
+            display("first", 0, 0, 160, 90),
+            display("second", 160, 0, 90, 160),
+            display("third", 250, 0, 200, 120),
+            display("fourth", 450, 0, 64, 64),
+        ];
+        let options = RenderOptions {
+            cache: false,
+            decode_concurrency: displays.len(),
+            ..RenderOptions::default()
+        };
+        let config = DisplayConfiguration::from_displays(displays.to_vec());
+        let images = vec![argument.clone(); displays.len()];
+        // Machines with a single core would run the jobs one after another
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(displays.len())
+            .build()
+            .unwrap();
+        let wallpaper = pool
+            .install(|| generate_wallpaper(&config, &images, &options))
+            .unwrap();
+        assert!(wallpaper.failures.is_empty());
+        for display in &displays {
+            let alone = DisplayConfiguration::from_displays(vec![display.clone()]);
+            let expected = generate_wallpaper(&alone, &images[..1], &options).unwrap();
+            let (width, height) = display.bounds.resolution();
+            let region = image::imageops::crop_imm(
+                &wallpaper.image,
+                display.bounds.min_x as u32,
+                0,
+                width,
+                height,
+            );
+            assert_eq!(region.to_image(), expected.image, "{}", display.name);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn limited_decode_concurrency_renders_the_same_wallpaper() {
+        let config = DisplayConfiguration::from_displays(vec![
+            display("first", 0, 0, 160, 90),
+            display("second", 160, 0, 90, 160),
+            display("third", 250, 0, 200, 120),
+        ]);
+        let images = [
+            png_argument(&pattern(400, 300)),
+            png_argument(&pattern(90, 310)),
+            png_argument(&pattern(250, 170)),
+        ];
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(images.len())
+            .build()
+            .unwrap();
+        let render = |decode_concurrency| {
+            let options = RenderOptions {
+                cache: false,
+                decode_concurrency,
+                ..RenderOptions::default()
+            };
+            let progressed = std::sync::atomic::AtomicUsize::new(0);
+            let wallpaper = pool
+                .install(|| {
+                    generate_wallpaper_with_progress(&config, &images, &options, &|_| {
+                        progressed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    })
+                })
+                .unwrap();
+            assert_eq!(progressed.into_inner(), images.len());
+            wallpaper.image
+        };
+        // One runs the jobs one by one, two leaves a partial last batch
+        let expected = render(3);
+        assert_eq!(render(1), expected);
+        assert_eq!(render(2), expected);
+    }
+
+    #[test]
+    fn color_names_take_precedence_over_hex() {
+        let navy = HexColor::from_u24(0x000080);
+        assert_eq!(color_parser("navy"), Ok(navy));
+        assert_eq!(color_parser("Navy"), Ok(navy));
+        assert_eq!(color_parser("#000080"), Ok(navy));
+        assert!(color_parser("nope").is_err());
+
+        let path = std::env::temp_dir().join(format!(
+            "wallpaper-aligner-test-{}-bg-name.png",
+            std::process::id()
+        ));
+        pattern(4, 4).save(&path).unwrap();
+        let argument = WallpaperArgument::from_str(&format!("bg=navy,{}", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        let Ok(WallpaperArgument::Image { background, .. }) = argument else {
+            panic!("expected an image");
+        };
+        assert_eq!(background, Some(navy));
+    }
+
+    #[test]
+    fn transparent_images_blend_into_the_background() {
+        // A red square in the middle of a transparent image, whose hidden color is black
+        let image = RgbaImage::from_fn(64, 64, |x, y| {
+            if (16..48).contains(&x) && (16..48).contains(&y) {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        let config = DisplayConfiguration::from_displays(vec![display("only", 0, 0, 100, 100)]);
+        let options = RenderOptions {
+            cache: false,
+            background: Some(HexColor::WHITE),
+            ..RenderOptions::default()
+        };
+        let wallpaper = generate_wallpaper(&config, &[png_argument(&image)], &options).unwrap();
+        // Red and white share the full red channel, so anything less is a dark fringe
+        assert!(wallpaper.image.pixels().all(|pixel| pixel[0] == 255));
+        assert_eq!(wallpaper.image.get_pixel(2, 2), &Rgb([255, 255, 255]));
+        assert_eq!(wallpaper.image.get_pixel(50, 50), &Rgb([255, 0, 0]));
+    }
+
+    #[test]
+    fn tints_blend_over_the_drawn_image() {
+        let white = png_argument(&RgbaImage::from_pixel(16, 16, Rgba([255, 255, 255, 255])));
+        let mut tinted = white.clone();
+        if let WallpaperArgument::Image { tint, .. } = &mut tinted {
+            *tint = Some(HexColor::rgba(0, 0, 0, 128));
+        }
+        let config = DisplayConfiguration::from_displays(vec![
+            display("tinted", 0, 0, 32, 32),
+            display("plain", 32, 0, 32, 32),
+        ]);
+        let options = RenderOptions {
+            cache: false,
+            ..RenderOptions::default()
+        };
+        let wallpaper = generate_wallpaper(&config, &[tinted, white], &options).unwrap();
+        assert_eq!(wallpaper.image.get_pixel(16, 16), &Rgb([127, 127, 127]));
+        assert_eq!(wallpaper.image.get_pixel(48, 16), &Rgb([255, 255, 255]));
+        assert!(WallpaperArgument::from_str("tint=#00000080,#FFFFFF").is_err());
+    }
+
+    #[test]
+    fn tiles_land_at_their_normalized_offsets() {
+        let red = png_argument(&RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 255])));
+        let blue = png_argument(&RgbaImage::from_pixel(4, 4, Rgba([0, 0, 255, 255])));
+        let config = DisplayConfiguration::from_displays(vec![
+            display("left", -1920, 0, 1920, 1080),
+            display("primary", 0, 200, 1280, 720),
+        ]);
+        assert_eq!(config.bounds.min_x, -1920);
+        let options = RenderOptions {
+            cache: false,
+            ..RenderOptions::default()
+        };
+        let wallpaper = generate_wallpaper(&config, &[red, blue], &options).unwrap();
+        let image = &wallpaper.image;
+        assert_eq!(image.dimensions(), (3200, 1080));
+        let (red, blue, black) = (Rgb([255, 0, 0]), Rgb([0, 0, 255]), Rgb([0, 0, 0]));
+        // The left display moves to the origin, the primary one right next to it
+        assert_eq!(image.get_pixel(0, 0), &red);
+        assert_eq!(image.get_pixel(1919, 1079), &red);
+        assert_eq!(image.get_pixel(1920, 200), &blue);
+        assert_eq!(image.get_pixel(3199, 919), &blue);
+        assert_eq!(image.get_pixel(1920, 199), &black);
+        assert_eq!(image.get_pixel(1920, 920), &black);
+    }
+
+    #[test]
+    fn fit_resolution_of_portrait_and_landscape_images() {
+        assert_eq!(fit_resolution((1080, 1920), (1920, 1080)), (608, 1080));
+        assert_eq!(fit_resolution((1920, 1080), (1080, 1920)), (1080, 608));
+        assert_eq!(fit_resolution((1080, 1920), (1080, 1920)), (1080, 1920));
+        assert_eq!(fit_resolution((3840, 2160), (1920, 1080)), (1920, 1080));
+        assert_eq!(fit_resolution((960, 540), (2560, 1440)), (2560, 1440));
+    }
+
+    #[test]
+    fn fit_resolution_of_almost_the_display_aspect_ratio() {
+        // A pixel off either way must neither overflow the display nor pick the wrong side
+        assert_eq!(fit_resolution((1921, 1080), (1920, 1080)), (1920, 1079));
+        assert_eq!(fit_resolution((1920, 1081), (1920, 1080)), (1918, 1080));
+        assert_eq!(fit_resolution((1079, 1920), (1080, 1920)), (1079, 1920));
+        assert_eq!(fit_resolution((3841, 2160), (1920, 1080)), (1920, 1080));
+    }
+
+    #[test]
+    fn fit_resolution_keeps_thin_images_visible() {
+        assert_eq!(fit_resolution((1, 10_000), (1920, 1080)), (1, 1080));
+        assert_eq!(fit_resolution((10_000, 1), (1920, 1080)), (1920, 1));
+    }
+
+    #[test]
+    fn aspect_pad_keeps_proportions_with_colored_bars() {
+        let square = png_argument(&RgbaImage::from_pixel(50, 50, Rgba([255, 0, 0, 255])));
+        let config = DisplayConfiguration::from_displays(vec![display("wide", 0, 0, 200, 100)]);
+        let options = RenderOptions {
+            cache: false,
+            aspect_pad: true,
+            pad_color: Some(HexColor::rgb(0, 255, 0)),
+            ..RenderOptions::default()
+        };
+        let wallpaper = generate_wallpaper(&config, &[square], &options).unwrap();
+        let image = &wallpaper.image;
+        assert_eq!(image.get_pixel(10, 50), &Rgb([0, 255, 0]));
+        assert_eq!(image.get_pixel(100, 50), &Rgb([255, 0, 0]));
+        assert_eq!(image.get_pixel(190, 50), &Rgb([0, 255, 0]));
+        // Stretched as a whole, the square stays as wide as the display is high
+        let red = |pixel: &&Rgb<u8>| pixel[0] > pixel[1];
+        let width = (0..200).map(|x| image.get_pixel(x, 50)).filter(red).count();
+        let height = (0..100)
+            .map(|y| image.get_pixel(100, y))
+            .filter(red)
+            .count();
+        assert_eq!((width, height), (100, 100));
+    }
+
+    /// Encodes `image` as a PNG whose eXIf chunk sets the given EXIF orientation
+    fn png_with_orientation(image: &RgbImage, orientation: u16) -> Vec<u8> {
+        fn crc32(bytes: &[u8]) -> u32 {
+            let mut crc = !0u32;
+            for &byte in bytes {
+                crc ^= byte as u32;
+                for _ in 0..8 {
+                    crc = if crc & 1 == 1 {
+                        (crc >> 1) ^ 0xEDB88320
+                    } else {
+                        crc >> 1
+                    };
+                }
+            }
+            !crc
+        }
+
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        // A little-endian TIFF header and an IFD with only the orientation tag
+        let mut exif =
+            b"II\x2a\x00\x08\x00\x00\x00\x01\x00\x12\x01\x03\x00\x01\x00\x00\x00".to_vec();
+        exif.extend_from_slice(&orientation.to_le_bytes());
+        exif.extend_from_slice(&[0; 6]);
+        let mut chunk = b"eXIf".to_vec();
+        chunk.extend_from_slice(&exif);
+        let mut segment = (exif.len() as u32).to_be_bytes().to_vec();
+        segment.extend_from_slice(&chunk);
+        segment.extend_from_slice(&crc32(&chunk).to_be_bytes());
+        // Right after the signature and the IHDR chunk
+        png.splice(33..33, segment);
+        png
+    }
+
+    #[test]
+    fn auto_orient_applies_all_exif_orientations() {
+        let stored = RgbImage::from_fn(3, 2, |x, y| Rgb([x as u8 * 100, y as u8 * 100, 0]));
+        let flip_h = image::imageops::flip_horizontal;
+        let expected = [
+            stored.clone(),
+            flip_h(&stored),
+            image::imageops::rotate180(&stored),
+            image::imageops::flip_vertical(&stored),
+            flip_h(&image::imageops::rotate90(&stored)),
+            image::imageops::rotate90(&stored),
+            flip_h(&image::imageops::rotate270(&stored)),
+            image::imageops::rotate270(&stored),
+        ];
+        for (orientation, expected) in (1..).zip(expected) {
+            let png = png_with_orientation(&stored, orientation);
+            let reader = || {
+                ImageReader::new(Cursor::new(&png))
+                    .with_guessed_format()
+                    .unwrap()
+            };
+            let (image, _) = decode(reader(), true, 0).unwrap();
+            assert_eq!(image.into_rgb8(), expected, "orientation {}", orientation);
+            let dimensions = read_dimensions(reader(), true).unwrap();
+            assert_eq!(
+                dimensions,
+                expected.dimensions(),
+                "orientation {}",
+                orientation
+            );
+            // Without auto-orient the pixels are used as stored
+            let (image, _) = decode(reader(), false, 0).unwrap();
+            assert_eq!(image.into_rgb8(), stored, "orientation {}", orientation);
+            assert_eq!(read_dimensions(reader(), false).unwrap(), (3, 2));
+        }
+    }
+
+    #[test]
+    fn cached_images_keep_their_warnings() {
+        let path = std::env::temp_dir().join(format!(
+            "wallpaper-aligner-test-{}-distorted.png",
+            std::process::id()
+        ));
+        pattern(400, 100).save(&path).unwrap();
+        let config = DisplayConfiguration::from_displays(vec![display("square", 0, 0, 100, 100)]);
+        let images = [file_argument(&path)];
+        let options = RenderOptions::default();
+        // The first render stores the image in the cache and the second one reads it from there
+        for _ in 0..2 {
+            let wallpaper = generate_wallpaper(&config, &images, &options).unwrap();
+            assert!(
+                wallpaper
+                    .warnings
+                    .iter()
+                    .any(|warning| warning.contains("distorted by 300%")),
+                "{:?}",
+                wallpaper.warnings
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cached_animations_keep_the_frame_warning() {
+        let path = std::env::temp_dir().join(format!(
+            "wallpaper-aligner-test-{}-animated.gif",
+            std::process::id()
+        ));
+        let frames = [Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])]
+            .map(|color| image::Frame::new(RgbaImage::from_pixel(8, 8, color)));
+        let mut encoder = image::codecs::gif::GifEncoder::new(File::create(&path).unwrap());
+        encoder.encode_frames(frames).unwrap();
+        drop(encoder);
+        let config = DisplayConfiguration::from_displays(vec![display("only", 0, 0, 16, 16)]);
+        let images = [file_argument(&path)];
+        let options = RenderOptions::default();
+        for _ in 0..2 {
+            let wallpaper = generate_wallpaper(&config, &images, &options).unwrap();
+            assert!(
+                wallpaper
+                    .warnings
+                    .iter()
+                    .any(|warning| warning.contains("only frame 1 is used")),
+                "{:?}",
+                wallpaper.warnings
+            );
+            assert_eq!(wallpaper.image.get_pixel(8, 8), &Rgb([255, 0, 0]));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn deterministic_renders_match_the_golden_hash() {
+        let config = DisplayConfiguration::from_displays(vec![
+            display("left", 0, 0, 192, 108),
+            display("right", 192, 0, 108, 192),
+        ]);
+        let images = [
+            png_argument(&pattern(250, 170)),
+            png_argument(&pattern(90, 310)),
+        ];
+        let options = RenderOptions {
+            mode: ResizeMode::Fill,
+            cache: false,
+            deterministic: true,
+            ..RenderOptions::default()
+        };
+        let wallpaper = generate_wallpaper(&config, &images, &options).unwrap();
+        // FNV-1a of the pixels produced by the scalar resizer. It must not depend on the CPU
+        // the test runs on, so a change of this value means golden images have to be redone
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for &byte in wallpaper.image.as_raw() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        assert_eq!(hash, 9131241193481684065);
+    }
+}
@@ -1,447 +1,2548 @@
-use std::collections::HashMap;
-use std::ffi::OsString;
 use std::fs::File;
-use std::io::BufReader;
-use std::os::windows::prelude::OsStringExt;
-use std::path::Path;
+use std::io::{Cursor, IsTerminal, Read};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use clap::{CommandFactory, Parser, ValueEnum};
+use clap::parser::ValueSource;
+use clap::{ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser, ValueEnum};
 use colored::Colorize;
-use fast_image_resize::{ResizeOptions, Resizer, SrcCropping};
 use hex_color::HexColor;
-use image::{DynamicImage, GenericImage, ImageReader, Rgb, RgbImage};
+use image::buffer::ConvertBuffer;
+use image::{ImageError, ImageFormat, ImageReader, Rgb, RgbImage, RgbaImage};
 use imageproc::rect::Rect;
-use inquire::validator::MinLengthValidator;
+use indicatif::{ProgressBar, ProgressStyle};
+use inquire::validator::{MinLengthValidator, Validation};
 use pluralizer::pluralize;
+use serde::{Deserialize, Serialize};
 use turbojpeg::Subsamp;
-use windows::Win32::Devices::Display::{
-    DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME, DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME, DISPLAYCONFIG_DEVICE_INFO_HEADER,
-    DISPLAYCONFIG_MODE_INFO, DISPLAYCONFIG_PATH_INFO,
-    DISPLAYCONFIG_SOURCE_DEVICE_NAME, DISPLAYCONFIG_TARGET_DEVICE_NAME, DisplayConfigGetDeviceInfo,
-    GetDisplayConfigBufferSizes, QDC_ONLY_ACTIVE_PATHS, QDC_VIRTUAL_MODE_AWARE,
-    QueryDisplayConfig,
+use wallpaper_aligner::backend::{LayoutFile, ManualLayout};
+use wallpaper_aligner::display::{Display, DisplayConfiguration, Rectangle};
+use wallpaper_aligner::{
+    color_parser, crop_parser, render, rotation_parser, Crop, FitBackground, Flip, ImageSource,
+    RenderError, RenderOptions, ResampleFilter, ResizeMode, WallpaperArgument,
 };
-use windows::Win32::Foundation::{BOOL, LPARAM, RECT, TRUE, WIN32_ERROR};
-use windows::Win32::Graphics::Gdi::{
-    EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFO, MONITORINFOEXW,
+#[cfg(windows)]
+use windows::Win32::UI::HiDpi::{
+    SetProcessDpiAwarenessContext, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2,
 };
 
-use crate::display::{Display, DisplayConfiguration};
+use crate::config::Config;
 
-mod display;
+mod cab;
+mod config;
+mod exif;
+mod jpeg;
+mod theme;
+mod watch;
 
-#[derive(ValueEnum, Debug, Copy, Clone)]
-enum ResizeMode {
-    /// Fills the entire display with the image. Stretches the image disproportionally as needed
-    Stretch,
-    /// Fills the entire display with the image. Scales the image proportionally
-    Fill,
-    /// Fits the entire image into the display. Scales the image proportionally
-    Fit,
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+enum OverlapPolicy {
+    /// Lists the overlapping displays and renders anyway
+    Warn,
+    /// Lists the overlapping displays and stops
+    Error,
+    /// Renders without a word, the later display covers the earlier one
+    LastWins,
+}
+
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+enum ColorChoice {
+    /// Colors messages unless stderr is redirected or the NO_COLOR environment variable is set
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+enum SortOrder {
+    Name,
+    /// When JPEG photos were taken as their EXIF data says, or when other files were last modified
+    Date,
 }
 
 /// A simple program to create wallpapers that span across all monitors from separate images
 #[derive(Parser, Debug)]
 #[command(about, arg_required_else_help = true)]
+// Each of these decides on its own which images go on the displays, so only one may be given
+#[command(group(
+    ArgGroup::new("sources")
+        .args(["images", "slideshow", "interactive", "from_metadata", "assign"])
+        .multiple(false)
+))]
 struct Args {
-    /// Print display information
+    /// Read the output name, mode, quality and images from a TOML file.
+    /// Options given on the command line take precedence over the file
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Print display information. Resolutions are in physical pixels, regardless of interface scaling
     #[arg(short = 'd', long = "displays", action)]
     show_displays: bool,
+    /// Print displays, supported formats and version as a single JSON document for frontends and exit
+    #[arg(long, action, conflicts_with = "images")]
+    probe: bool,
+    /// Print the display layout as JSON with coordinates as Windows reports them and the size of
+    /// every display, then exit
+    #[arg(long = "displays-json", action, conflicts_with = "images")]
+    displays_json: bool,
+    /// Use this display layout instead of the detected one, e.g. to preview another arrangement.
+    /// Either a file written with --displays-json or x,y,width,height and an optional name for every display,
+    /// separated by semicolons, e.g. "0,0,1920,1080,Left;1920,0,2560,1440,Right"
+    #[arg(long, value_name = "LAYOUT", value_parser = layout_parser)]
+    layout: Option<String>,
+    /// Write identify.jpg with the number of each display drawn large on it and exit.
+    /// Set it as a spanned wallpaper to see which display comes at which position in the list of images
+    #[arg(long, action, conflicts_with = "images")]
+    identify: bool,
+    /// Print how display names are resolved to stderr, as one JSON object per display path with
+    /// its source, target and adapter ids, the names found for it and why any of them is missing
+    #[arg(long = "debug-displays", action)]
+    debug_displays: bool,
+    /// Print where each display lands on the wallpaper to stderr
+    #[arg(short, long, action)]
+    verbose: bool,
+    /// Don't show a progress bar while images are prepared
+    #[arg(short, long, action)]
+    quiet: bool,
+    /// When to color messages
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
     /// Overwrite output file if it already exists without confirmation
     #[arg(short = 'f', long = "force", action)]
     overwrite: bool,
-    /// Name of the output image
-    #[arg(short, long, default_value = "wallpaper.jpg", value_parser = output_parser)]
-    output: String,
+    /// Name of the output image. Saved as a lossless PNG if it ends with .png, as a WebP if it ends with .webp,
+    /// as a BMP if it ends with .bmp and as a JPEG otherwise. Several names separated by commas,
+    /// e.g. wall.png,wall.jpg, save the same wallpaper in each of their formats
+    #[arg(short, long, default_value = "wallpaper.jpg", value_delimiter = ',', value_parser = output_parser)]
+    output: Vec<String>,
+    /// Save every display to its own file named after the output with the display number appended,
+    /// e.g. wallpaper_1.jpg, for tools that set a wallpaper per display
+    #[arg(long, action, conflicts_with = "export_theme")]
+    split: bool,
+    /// Like --split, but appends the display name instead of the number
+    #[arg(long = "split-by-name", action, conflicts_with = "export_theme")]
+    split_by_name: bool,
+    /// Skip the last displays when fewer images than displays are given instead of stopping
+    #[arg(long = "allow-partial", action)]
+    allow_partial: bool,
+    /// Exit with 0 even if some images couldn't be drawn. Their displays show the --background color
+    /// or --backdrop either way
+    #[arg(long = "ignore-errors", action)]
+    ignore_errors: bool,
+    /// Directory to save the output image to, created if it doesn't exist
+    #[arg(long = "output-dir", value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+    /// Render the wallpaper at FACTOR times the resolution of the displays,
+    /// e.g. 0.5 for a quick preview or 2 to supersample
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0, value_parser = scale_parser)]
+    scale: f64,
     /// Resize mode to apply if a source image resolution doesn't match display one
     #[arg(short, long, value_enum, default_value_t = ResizeMode::Stretch)]
     mode: ResizeMode,
+    /// Resampling filter used to scale images. Sharper filters take longer and can add halos
+    /// around hard edges
+    #[arg(long, value_enum, value_name = "FILTER", default_value_t = ResampleFilter::Lanczos3)]
+    filter: ResampleFilter,
+    /// Keep pixel art crisp: scale images by the largest whole factor that fits with
+    /// nearest-neighbor sampling and center them on their displays
+    #[arg(long = "pixel-art", action, conflicts_with = "filter")]
+    pixel_art: bool,
+    /// Keep images as they are stored instead of rotating them upright as their EXIF orientation says
+    #[arg(long = "no-auto-orient", action)]
+    no_auto_orient: bool,
+    /// Add N from -255 to 255 to every color channel of each source image.
+    /// Negative values darken, 0 leaves images unchanged
+    #[arg(long, value_name = "N", default_value_t = 0, allow_negative_numbers = true, value_parser = brightness_parser)]
+    brightness: i32,
+    /// Change the contrast of each source image by N percent from -100 to 100, spreading colors away from
+    /// or pulling them toward middle gray. 0 leaves images unchanged
+    #[arg(long, value_name = "N", default_value_t = 0.0, allow_negative_numbers = true, value_parser = contrast_parser)]
+    contrast: f32,
+    /// Convert every source image to grayscale. Colors and gradients are left as they are
+    #[arg(long, action, conflicts_with = "saturation")]
+    grayscale: bool,
+    /// Multiply the saturation of every source image by FACTOR, e.g. 0.5 for muted colors.
+    /// Colors and gradients are left as they are
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0, value_parser = saturation_parser)]
+    saturation: f32,
+    /// Crop every source image to a centered square before applying the resize mode
+    #[arg(long, action)]
+    square: bool,
+    /// In Fill mode, the part of each image to keep when cropping: center, top, bottom, left, right,
+    /// a rule-of-thirds point like upper-left, center-right or lower-center,
+    /// or x,y fractions of the overflow to crop from the left and top, e.g. 0.5,0.2
+    #[arg(long, value_name = "FOCUS", value_parser = focus_parser, conflicts_with = "smart_crop")]
+    focus: Option<(f64, f64)>,
+    /// In Fill mode, crop toward the most detailed part of each image instead of its center.
+    /// Noticeably slower on large images
+    #[arg(long = "smart-crop", action)]
+    smart_crop: bool,
+    /// Use only the X,Y,WIDTH,HEIGHT region in pixels of source images shown upright,
+    /// unless they are prefixed with their own crop=
+    #[arg(long, value_name = "X,Y,WIDTH,HEIGHT", value_parser = crop_parser)]
+    crop: Option<Crop>,
+    /// Mirror every source image left to right. Flips happen before rotate= turns an image
+    #[arg(long = "flip-h", action)]
+    flip_h: bool,
+    /// Mirror every source image top to bottom
+    #[arg(long = "flip-v", action)]
+    flip_v: bool,
+    /// Always decode and resize images instead of reusing the results of earlier runs.
+    /// They are kept in %TEMP%\wallpaper-aligner, which can be deleted at any time,
+    /// and the least recently used ones are removed once they take more than 512 MB
+    #[arg(long = "no-cache", action)]
+    no_cache: bool,
+    /// Decode and resize an image only once when it is used on several displays of the same resolution
+    #[arg(long = "dedupe-sources", action)]
+    dedupe_sources: bool,
+    /// In Stretch mode, pad each image with bars to the display aspect ratio first,
+    /// so it is scaled without distortion or cropping
+    #[arg(long = "aspect-pad", action)]
+    aspect_pad: bool,
+    /// Print which image goes to which display at what size and position without rendering or saving anything
+    #[arg(long = "dry-run", action)]
+    dry_run: bool,
+    /// Print how much of each source image was kept and how much of the canvas is left as background
+    #[arg(long, action)]
+    stats: bool,
+    /// Give up on decoding an image after this many seconds and treat it as a decoding failure.
+    /// An abandoned decode keeps running in the background and holds its memory until it finishes
+    #[arg(long = "decode-timeout", value_name = "SECS")]
+    decode_timeout: Option<u64>,
+    /// Decode and resize at most N images at the same time. Each one holds its full decoded image,
+    /// so higher values are faster with many images but take more memory
+    #[arg(long = "decode-concurrency", value_name = "N", default_value_t = 2, value_parser = display_number_parser)]
+    decode_concurrency: usize,
+    /// Give up on downloading an image given as a URL after this many seconds
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    timeout: u64,
+    /// Use frame N of animated GIF, PNG and WebP images instead of the first one
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = display_number_parser)]
+    frame: usize,
+    /// Fill the whole canvas with a blurred copy of this image, showing behind letterbox bars
+    /// and skipped displays instead of black
+    #[arg(long, value_name = "PATH")]
+    backdrop: Option<PathBuf>,
+    /// Strength of the --backdrop blur
+    #[arg(long = "backdrop-sigma", value_name = "SIGMA", default_value_t = 50.0, value_parser = sigma_parser)]
+    backdrop_sigma: f32,
+    /// Color of the bars around images that don't cover their display and of transparent areas.
+    /// Black by default
+    #[arg(long = "pad-color", value_name = "COLOR", value_parser = color_parser)]
+    pad_color: Option<HexColor>,
+    /// Color of skipped displays and of the canvas wherever nothing else is drawn. Black by default
+    #[arg(long, value_name = "COLOR", value_parser = color_parser)]
+    background: Option<HexColor>,
+    /// Color of the canvas between displays that aren't flush or next to a shorter display,
+    /// e.g. to match the bezels. Shows the --background or --backdrop by default
+    #[arg(long = "gap-color", value_name = "COLOR", value_parser = color_parser)]
+    gap_color: Option<HexColor>,
+    /// What to show in the bars around images in Fit mode
+    #[arg(long = "fit-background", value_enum, default_value_t = FitBackground::Color)]
+    fit_background: FitBackground,
+    /// Strength of the --fit-background blur
+    #[arg(long = "fit-blur-sigma", value_name = "SIGMA", default_value_t = 20.0, value_parser = sigma_parser)]
+    fit_blur_sigma: f32,
+    /// Order of the images a directory or glob pattern stands for. Images taken or modified at the
+    /// same time are ordered by name
+    #[arg(long = "sort-by", value_enum, default_value_t = SortOrder::Name)]
+    sort_by: SortOrder,
+    /// What to do when displays overlap, e.g. because they are cloned.
+    /// The image of the later display covers the earlier one where they overlap
+    #[arg(long = "on-overlap", value_enum, default_value_t = OverlapPolicy::Warn)]
+    on_overlap: OverlapPolicy,
+    /// Write the name and resolution of each display into its top left corner to check the layout
+    #[arg(long, action)]
+    label: bool,
+    /// Color of the --label text, white or black depending on what's under it by default
+    #[arg(long = "label-color", value_name = "COLOR", value_parser = color_parser, requires = "label")]
+    label_color: Option<HexColor>,
+    /// Darken the outer N pixels of each display toward the edges it shares with another display
+    #[arg(long = "edge-blend", value_name = "N", default_value_t = 0)]
+    edge_blend: u32,
+    /// Draw gradients with a subtle ordered dither, which hides their banding even once they are
+    /// compressed
+    #[arg(long, action)]
+    dither: bool,
+    /// Copy the composed region of display SRC onto display DST, resizing it if their resolutions differ.
+    /// Displays are numbered as in the display list. Can be repeated
+    #[arg(long, value_name = "SRC:DST", value_parser = mirror_parser)]
+    mirror: Vec<(usize, usize)>,
+    /// Order displays as they are read on a wall physically rotated clockwise by 0, 90, 180 or 270 degrees.
+    /// With 90 the logical left-most display comes first, since it is the top one on the wall.
+    /// Without this option displays are ordered top-to-bottom, then left-to-right by their top left corners
+    #[arg(long = "rotate-layout", value_name = "DEGREES", value_parser = rotation_parser)]
+    rotate_layout: Option<u32>,
+    /// Put the primary display first and order the rest as usual
+    #[arg(long = "primary-first", action)]
+    primary_first: bool,
+    /// Compare the display layout with the fingerprint saved in STATEFILE, save the current one and exit.
+    /// Exits with 0 if the layout changed (or STATEFILE didn't exist), 1 if it didn't and 2 on errors
+    #[arg(
+        long = "detect-changes",
+        value_name = "STATEFILE",
+        conflicts_with = "images"
+    )]
+    detect_changes: Option<PathBuf>,
+    /// Check whether the wallpaper FILE still matches the size of the current display layout and exit.
+    /// Exits with 0 if it does, 1 if it doesn't and 2 if FILE can't be read
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["images", "detect_changes"])]
+    verify: Option<PathBuf>,
+    /// Swap the exit codes of --detect-changes, so an unchanged layout exits with 0
+    #[arg(long = "exit-zero-if-unchanged", action, requires = "detect_changes")]
+    exit_zero_if_unchanged: bool,
+    /// Also bundle the wallpaper into a Windows theme pack that spans it across all displays when opened
+    #[arg(long = "export-theme", value_name = "FILE", value_parser = theme_parser)]
+    export_theme: Option<PathBuf>,
+    /// Also write a JSON file next to the wallpaper that records the version, time, display layout,
+    /// images and settings it was made with
+    #[arg(long, action)]
+    metadata: bool,
+    /// Render again with the images and options recorded in a --metadata FILE.
+    /// Asks before rendering if the display layout changed in the meantime
+    #[arg(long = "from-metadata", value_name = "FILE", conflicts_with = "config")]
+    from_metadata: Option<PathBuf>,
+    /// Arguments of the render loaded with --from-metadata, recorded again in place of its own
+    #[arg(skip)]
+    recorded_arguments: Option<Vec<String>>,
+    /// JPEG and WebP compression quality from 1 to 100. WebP is saved losslessly at 100
+    #[arg(long, default_value_t = 100, value_parser = quality_parser)]
+    quality: i32,
+    /// Bits per pixel of PNG and BMP wallpapers, 24 or 32. 32 adds an opaque alpha channel for tools
+    /// that only take 32-bit images
+    #[arg(long = "output-bitdepth", value_name = "BITS", default_value_t = 24, value_parser = bitdepth_parser)]
+    output_bitdepth: u8,
+    /// Lower the JPEG quality as little as needed to keep the wallpaper within SIZE, e.g. 500KB or 5MB.
+    /// --quality is the highest quality tried
+    #[arg(long = "max-size", value_name = "SIZE", value_parser = size_parser)]
+    max_size: Option<u64>,
+    /// Produce byte-identical output for identical inputs on any machine.
+    /// Resizing and JPEG compression use their portable code instead of SIMD routines, which makes
+    /// them several times slower. The encoder settings are fixed: JPEG without chroma subsampling
+    /// at --quality, PNG with default compression and WebP at --quality
+    #[arg(long, action)]
+    deterministic: bool,
+    /// Tile all images as a grid onto display N instead of putting one image on each display.
+    /// Other displays are left as background
+    #[arg(long, value_name = "N", value_parser = display_number_parser)]
+    mosaic: Option<usize>,
+    /// Number of --mosaic grid columns, picked from the number of images and display aspect ratio by default
+    #[arg(long = "mosaic-cols", value_name = "COLS", requires = "mosaic", value_parser = display_number_parser)]
+    mosaic_cols: Option<usize>,
+    /// Keep running after writing the wallpaper and write it again whenever displays are connected,
+    /// disconnected or rearranged. Outside of Windows the layout is checked every few seconds
+    #[arg(long = "watch-layout", action)]
+    watch_layout: bool,
+    /// Keep running after writing the wallpaper and write it again whenever a file changes
+    /// in a directory the images come from
+    #[arg(long, action, conflicts_with = "watch_layout")]
+    watch: bool,
+    /// Cycle through sets of images from DIR, one set per subdirectory, or one per as many images
+    /// as there are displays if DIR has no subdirectories. Sets that don't match the displays are skipped
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["watch", "watch_layout"])]
+    slideshow: Option<PathBuf>,
+    /// Ask which image or color goes on each display instead of taking them from the command line
+    #[arg(long, action)]
+    interactive: bool,
+    /// Seconds to show each --slideshow set for
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 300,
+        requires = "slideshow"
+    )]
+    interval: u64,
+    /// Text to embed as a comment in the output JPEG
+    #[arg(long, value_parser = comment_parser)]
+    comment: Option<String>,
+    /// Put an image or color on the display with the given name instead of listing them in display order,
+    /// e.g. --assign "DELL U2720Q=photo.jpg". Takes the same values as the list of images.
+    /// Displays without an assignment are skipped. Can be repeated
+    #[arg(long, value_name = "NAME=IMAGE", value_parser = assignment_parser, conflicts_with = "mosaic")]
+    assign: Vec<(String, WallpaperArgument)>,
     /// A list of images or colors in hex (e.g. #FF0000 for red) in order of displays to generate wallpaper from.
-    /// Use empty string ("") to skip a display (will use black color instead)
+    /// Colors with alpha (e.g. #FF000080) are blended over the --backdrop instead of covering it.
+    /// CSS color names like red or midnightblue work too and take precedence over files with the same name,
+    /// use .\red to pick such a file.
+    /// Use empty string ("") to skip a display (will show the --background color instead).
+    /// A directory stands for all images in it, sorted by --sort-by.
+    /// A glob pattern like shots\*.jpg stands for all files it matches, sorted by --sort-by.
+    /// Use - to read an image from standard input, e.g. when piping it from another program.
+    /// An http:// or https:// URL stands for the image it points to, downloaded on every render.
+    /// Prefix an image with bg=<color>, to use that color for its letterbox bars and transparent areas,
+    /// e.g. bg=#202020,photo.jpg.
+    /// Use grad:#FF0000-#0000FF for a gradient from top to bottom through any number of colors,
+    /// grad:h:#000-#FFF for one from left to right, or grad:45:#000-#FFF for one at an angle
+    /// in degrees clockwise from left-to-right, here from the top left to the bottom right corner.
+    /// Use radial:#FFF-#000 for a gradient from the middle of the display out to its corners,
+    /// or radial:0.3,0.7:#FFF-#000 to move its center to 30% of the width and 70% of the height.
+    /// Suffix an image with :<mode> to resize it with another mode than --mode, e.g. photo.jpg:fit.
+    /// Prefix an image with rotate=<degrees>, to turn it clockwise by 90, 180 or 270 degrees
+    /// before it is cropped and resized, e.g. rotate=90,photo.jpg.
+    /// Prefix an image with crop=<x>,<y>,<width>,<height>, to use only that region of it in pixels
+    /// as it is shown upright, e.g. crop=100,100,800,600,photo.jpg.
+    /// Prefix an image with flip=h, flip=v or flip=hv, to mirror it left to right, top to bottom
+    /// or both on top of --flip-h and --flip-v. Images are flipped before they are rotated.
+    /// Prefix an image with tint=<hex color>, to blend that color over the display after the image
+    /// is drawn, e.g. tint=#00000060,photo.jpg to darken it.
+    /// Colors always cover the whole display and don't take a mode
     #[arg(allow_hyphen_values = true)]
     images: Vec<WallpaperArgument>,
 }
 
+fn is_png(name: &str) -> bool {
+    name.to_lowercase().ends_with(".png")
+}
+
+fn is_webp(name: &str) -> bool {
+    name.to_lowercase().ends_with(".webp")
+}
+
+fn is_bmp(name: &str) -> bool {
+    name.to_lowercase().ends_with(".bmp")
+}
+
 fn output_parser(name: &str) -> Result<String, String> {
     let lowercase = name.to_lowercase();
-    if !lowercase.ends_with(".jpeg") && !lowercase.ends_with(".jpg") {
+    if !lowercase.ends_with(".jpeg")
+        && !lowercase.ends_with(".jpg")
+        && !is_png(name)
+        && !is_webp(name)
+        && !is_bmp(name)
+    {
         return Ok(name.to_owned() + ".jpg");
     }
     Ok(name.to_owned())
 }
 
-#[derive(Debug, Clone)]
-enum WallpaperArgument {
-    Image(Arc<File>, String),
-    Color(HexColor),
+/// Splits comma separated output names the way --output does
+fn outputs_parser(names: &str) -> Vec<String> {
+    names
+        .split(',')
+        .map(|name| output_parser(name).expect("function does not return Err"))
+        .collect()
 }
 
-impl FromStr for WallpaperArgument {
-    type Err = &'static str;
+fn scale_parser(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(scale) if scale > 0.0 && scale.is_finite() => Ok(scale),
+        _ => Err("must be a positive number".to_owned()),
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.is_empty() {
-            return Ok(WallpaperArgument::Color(HexColor::BLACK));
-        }
-        if let Ok(color) = HexColor::parse_rgb(s) {
-            return Ok(WallpaperArgument::Color(color));
-        }
-        if let Ok(file) = File::open(s) {
-            return Ok(WallpaperArgument::Image(Arc::new(file), s.to_owned()));
-        }
-        Err("Unable to parse color or open file")
+fn sigma_parser(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(sigma) if sigma > 0.0 => Ok(sigma),
+        _ => Err("must be a positive number".to_owned()),
+    }
+}
+
+/// Rule-of-thirds points --focus takes by name, where most off-center subjects sit
+const THIRDS: [(&str, (f64, f64)); 8] = [
+    ("upper-left", (1.0 / 3.0, 1.0 / 3.0)),
+    ("upper-center", (0.5, 1.0 / 3.0)),
+    ("upper-right", (2.0 / 3.0, 1.0 / 3.0)),
+    ("center-left", (1.0 / 3.0, 0.5)),
+    ("center-right", (2.0 / 3.0, 0.5)),
+    ("lower-left", (1.0 / 3.0, 2.0 / 3.0)),
+    ("lower-center", (0.5, 2.0 / 3.0)),
+    ("lower-right", (2.0 / 3.0, 2.0 / 3.0)),
+];
+
+fn focus_parser(value: &str) -> Result<(f64, f64), String> {
+    let name = value.to_lowercase();
+    match name.as_str() {
+        "center" => return Ok((0.5, 0.5)),
+        "top" => return Ok((0.5, 0.0)),
+        "bottom" => return Ok((0.5, 1.0)),
+        "left" => return Ok((0.0, 0.5)),
+        "right" => return Ok((1.0, 0.5)),
+        _ => {}
+    }
+    if let Some((_, focus)) = THIRDS.iter().find(|(anchor, _)| *anchor == name) {
+        return Ok(*focus);
+    }
+    let error = || {
+        "must be center, top, bottom, left, right, a rule-of-thirds point like upper-left \
+         or two numbers from 0 to 1, e.g. 0.5,0.2"
+            .to_owned()
+    };
+    let (x, y) = value.split_once(',').ok_or_else(error)?;
+    let x = x.trim().parse::<f64>().map_err(|_| error())?;
+    let y = y.trim().parse::<f64>().map_err(|_| error())?;
+    if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+        return Err(error());
+    }
+    Ok((x, y))
+}
+
+fn brightness_parser(value: &str) -> Result<i32, String> {
+    match value.parse::<i32>() {
+        Ok(brightness @ -255..=255) => Ok(brightness),
+        _ => Err("must be a number from -255 to 255".to_owned()),
+    }
+}
+
+fn contrast_parser(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(contrast) if (-100.0..=100.0).contains(&contrast) => Ok(contrast),
+        _ => Err("must be a number from -100 to 100".to_owned()),
+    }
+}
+
+fn saturation_parser(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(saturation) if saturation >= 0.0 => Ok(saturation),
+        _ => Err("must be a non-negative number".to_owned()),
+    }
+}
+
+fn quality_parser(value: &str) -> Result<i32, String> {
+    match value.parse::<i32>() {
+        Ok(quality @ 1..=100) => Ok(quality),
+        _ => Err("must be a number from 1 to 100".to_owned()),
+    }
+}
+
+fn bitdepth_parser(value: &str) -> Result<u8, String> {
+    match value.parse::<u8>() {
+        Ok(bits @ (24 | 32)) => Ok(bits),
+        _ => Err("must be 24 or 32".to_owned()),
+    }
+}
+
+/// Parses a number of bytes with an optional B, KB, MB, KiB or MiB unit
+fn size_parser(value: &str) -> Result<u64, String> {
+    let lowercase = value.trim().to_lowercase();
+    let split = lowercase
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(lowercase.len());
+    let (number, unit) = lowercase.split_at(split);
+    let multiplier = match unit.trim() {
+        "" | "b" => 1.0,
+        "k" | "kb" => 1e3,
+        "m" | "mb" => 1e6,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        _ => return Err("must be a size like 800KB or 5MB".to_owned()),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number * multiplier >= 1.0 => Ok((number * multiplier) as u64),
+        _ => Err("must be a size like 800KB or 5MB".to_owned()),
+    }
+}
+
+fn theme_parser(name: &str) -> Result<PathBuf, String> {
+    if !name.to_lowercase().ends_with(".deskthemepack") {
+        return Ok(PathBuf::from(name.to_owned() + ".deskthemepack"));
+    }
+    Ok(PathBuf::from(name))
+}
+
+fn comment_parser(comment: &str) -> Result<String, String> {
+    if comment.len() > jpeg::MAX_COMMENT_LENGTH {
+        return Err(format!(
+            "must not be longer than {} bytes",
+            jpeg::MAX_COMMENT_LENGTH
+        ));
+    }
+    Ok(comment.to_owned())
+}
+
+fn mirror_parser(value: &str) -> Result<(usize, usize), String> {
+    let error = || "must be two display numbers separated by a colon, e.g. 1:3".to_owned();
+    let (source, target) = value.split_once(':').ok_or_else(error)?;
+    let source = source.trim().parse::<usize>().map_err(|_| error())?;
+    let target = target.trim().parse::<usize>().map_err(|_| error())?;
+    if source == 0 || target == 0 {
+        return Err("display numbers start at 1".to_owned());
+    }
+    Ok((source, target))
+}
+
+fn display_number_parser(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => Err("must be a positive number".to_owned()),
+    }
+}
+
+fn layout_parser(value: &str) -> Result<String, String> {
+    if !Path::new(value).is_file() {
+        ManualLayout::from_str(value)?;
+    }
+    Ok(value.to_owned())
+}
+
+fn assignment_parser(value: &str) -> Result<(String, WallpaperArgument), String> {
+    let (name, argument) = value
+        .split_once('=')
+        .ok_or_else(|| "must be a display name and an image separated by =".to_owned())?;
+    let argument = WallpaperArgument::from_str(argument).map_err(str::to_owned)?;
+    if let WallpaperArgument::Directory(_)
+    | WallpaperArgument::Pattern(_)
+    | WallpaperArgument::Stdin = argument
+    {
+        return Err(
+            "must be a single image or color, not a directory, pattern or standard input"
+                .to_owned(),
+        );
     }
+    Ok((name.trim().to_owned(), argument))
 }
 
 fn main() {
-    let mut args = Args::parse();
-    if !args.show_displays && args.images.is_empty() {
+    // Windows spans wallpapers over the desktop in physical pixels, but reports display bounds
+    // scaled to the DPI of the primary display to processes that aren't aware of per-display DPI.
+    // This fails if the awareness was already set, which leaves whatever was set in place
+    #[cfg(windows)]
+    let _ = unsafe { SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) };
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let mut recorded_layout = None;
+    if let Some(path) = args.from_metadata.clone() {
+        let Some((recorded, layout)) = load_metadata(&path, &args) else {
+            return;
+        };
+        args = recorded;
+        recorded_layout = Some(layout);
+    }
+    colored::control::set_override(match args.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        // Status messages go to stderr, so that is where colors have to make sense
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && std::io::stderr().is_terminal()
+        }
+    });
+    if let Some(path) = args.config.clone() {
+        if !apply_config(&mut args, &matches, &path) {
+            return;
+        }
+    }
+    if args.deterministic {
+        // Makes libjpeg-turbo use its portable C code instead of CPU specific SIMD routines.
+        // Changing the environment is only sound while no other thread runs, so this has to
+        // happen before anything is decoded, downloaded or rendered
+        std::env::set_var("JSIMD_FORCENONE", "1");
+    }
+    if !read_stdin(&mut args.images) {
+        return;
+    }
+    // Kept for --watch, so directories and patterns pick up new images
+    let sources = args.images.clone();
+    args.images = match expand_arguments(std::mem::take(&mut args.images), args.sort_by) {
+        Some(images) => images,
+        None => return,
+    };
+    if !args.show_displays
+        && !args.debug_displays
+        && !args.probe
+        && !args.displays_json
+        && !args.identify
+        && args.slideshow.is_none()
+        && args.detect_changes.is_none()
+        && args.verify.is_none()
+        && !args.interactive
+        && args.images.is_empty()
+        && args.assign.is_empty()
+    {
         let _ = Args::command().print_help();
         return;
     }
 
-    let mut config = get_display_configuration();
+    let Some(config) = detect_displays(&args) else {
+        return;
+    };
+    if args.probe {
+        println!("{}", probe(&config));
+        return;
+    }
+    if args.displays_json {
+        println!("{}", displays_json(&config));
+        return;
+    }
     if args.show_displays {
         config.show_displays();
     }
-    if args.images.is_empty() {
+    if let Some(path) = &args.verify {
+        std::process::exit(verify(&config, path, args.scale));
+    }
+    if let Some(recorded) = recorded_layout {
+        if recorded.fingerprint() != config.fingerprint() {
+            eprintln!(
+                "{} The display layout changed since the metadata was written",
+                "!".yellow()
+            );
+            let proceed = inquire::Confirm::new("Render for the current layout?")
+                .with_default(false)
+                .prompt();
+            if !matches!(proceed, Ok(true)) {
+                return;
+            }
+        }
+    }
+    if let Some(state_file) = &args.detect_changes {
+        std::process::exit(detect_changes(
+            &config,
+            state_file,
+            args.exit_zero_if_unchanged,
+        ));
+    }
+    if args.identify {
+        identify(&args, config);
+        return;
+    }
+    if args.interactive {
+        match pick_images(&config) {
+            Some(images) => args.images = images,
+            None => return,
+        }
+    }
+    if args.images.is_empty() && args.assign.is_empty() && args.slideshow.is_none() {
+        return;
+    }
+    if let Some(dir) = &args.output_dir {
+        if !args.dry_run && !dir.is_dir() && !create_output_dir(dir, args.overwrite) {
+            return;
+        }
+        args.output = args
+            .output
+            .iter()
+            .map(|output| output_path(Some(dir), output))
+            .collect();
+    }
+    if !args.dry_run
+        && !args
+            .output
+            .iter()
+            .all(|output| check_output_dir(output, args.overwrite))
+    {
         return;
     }
 
-    if config.displays.len() != args.images.len() {
-        println!(
-            "{} Detected {} but you provided {}, please check the arguments and try again.",
+    if let Some(dir) = args.slideshow.clone() {
+        slideshow(&mut args, &dir);
+        return;
+    }
+
+    let fingerprint = config.fingerprint();
+    // Kept for --watch-layout, as rendering may pad the images for the current displays
+    let images = args.images.clone();
+    let layout = config.clone();
+    let rendered = render(&mut args, config);
+    if args.watch {
+        watch_sources(&mut args, sources, &layout);
+    }
+    if args.watch_layout {
+        watch_layout(&mut args, images, fingerprint);
+    }
+    if !rendered {
+        std::process::exit(1);
+    }
+}
+
+/// Name of the image read from standard input in messages and --metadata files
+const STDIN_FILENAME: &str = "<stdin>";
+
+/// Replaces the - argument with the image piped to standard input, which can be read only once
+fn read_stdin(arguments: &mut [WallpaperArgument]) -> bool {
+    let mut placeholders = arguments
+        .iter_mut()
+        .filter(|argument| matches!(argument, WallpaperArgument::Stdin));
+    let Some(argument) = placeholders.next() else {
+        return true;
+    };
+    if placeholders.next().is_some() {
+        eprintln!(
+            "{} Only one image can be read from standard input.",
+            "!".yellow()
+        );
+        return false;
+    }
+    let mut data = Vec::new();
+    if let Err(err) = std::io::stdin().read_to_end(&mut data) {
+        eprintln!(
+            "{} Unable to read image from standard input: {}",
             "!".yellow(),
-            pluralize("display", config.displays.len() as isize, true),
-            pluralize("image", args.images.len() as isize, true)
+            err
         );
-        if !args.show_displays {
-            config.show_displays();
+        return false;
+    }
+    *argument = WallpaperArgument::Image {
+        source: ImageSource::Memory(data.into()),
+        filename: STDIN_FILENAME.to_owned(),
+        background: None,
+        mode: None,
+        rotation: 0,
+        crop: None,
+        flip: Flip::default(),
+        tint: None,
+    };
+    true
+}
+
+/// Replaces every directory argument with the images in it and every glob pattern with the files
+/// it matches, both sorted in `order`
+fn expand_arguments(
+    arguments: Vec<WallpaperArgument>,
+    order: SortOrder,
+) -> Option<Vec<WallpaperArgument>> {
+    let mut expanded = Vec::with_capacity(arguments.len());
+    for argument in arguments {
+        let mut files = match argument {
+            WallpaperArgument::Directory(path) => directory_images(&path)?,
+            WallpaperArgument::Pattern(pattern) => pattern_matches(&pattern)?,
+            argument => {
+                expanded.push(argument);
+                continue;
+            }
+        };
+        if order == SortOrder::Date {
+            sort_by_date(&mut files);
         }
-        return;
+        expanded.extend(open_images(files)?);
     }
-    while !args.overwrite && Path::new(&args.output).exists() {
-        let message = format!(
-            "Output file '{}' already exists. Overwrite?",
-            args.output.yellow()
+    Some(expanded)
+}
+
+fn open_images(files: Vec<PathBuf>) -> Option<Vec<WallpaperArgument>> {
+    let mut images = Vec::with_capacity(files.len());
+    for path in files {
+        let filename = path.to_string_lossy().into_owned();
+        match File::open(&path) {
+            Ok(file) => images.push(WallpaperArgument::Image {
+                source: ImageSource::File(Arc::new(file)),
+                filename,
+                background: None,
+                mode: None,
+                rotation: 0,
+                crop: None,
+                flip: Flip::default(),
+                tint: None,
+            }),
+            Err(err) => {
+                eprintln!(
+                    "{} Unable to open image '{}': {}",
+                    "!".yellow(),
+                    filename,
+                    err
+                );
+                return None;
+            }
+        }
+    }
+    Some(images)
+}
+
+fn sort_by_name(files: &mut [PathBuf]) {
+    files.sort_by_key(|file| file.to_string_lossy().to_lowercase());
+}
+
+/// Sorts files already sorted by name by when they were taken or modified, keeping the order of
+/// files with the same time. Files whose time can't be read go last
+fn sort_by_date(files: &mut [PathBuf]) {
+    files.sort_by_cached_key(|file| {
+        let time = capture_time(file).or_else(|| {
+            let modified = std::fs::metadata(file).and_then(|metadata| metadata.modified());
+            let elapsed = modified.ok()?.duration_since(UNIX_EPOCH).ok()?;
+            Some(elapsed.as_secs() as i64)
+        });
+        (time.is_none(), time)
+    });
+}
+
+/// Reads when a JPEG photo was taken from the EXIF data at the start of the file
+fn capture_time(path: &Path) -> Option<i64> {
+    // EXIF data is limited to a single segment of 64 KiB, which may follow a JFIF segment
+    const HEADER_LENGTH: u64 = 128 * 1024;
+    let mut header = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(HEADER_LENGTH)
+        .read_to_end(&mut header)
+        .ok()?;
+    exif::date_time_original(jpeg::exif(&header)?)
+}
+
+/// Whether `path` has the extension of an image format that can be decoded
+fn is_image(path: &Path) -> bool {
+    let heif = path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("heic") || extension.eq_ignore_ascii_case("heif")
+    });
+    ImageFormat::from_path(path).is_ok_and(|format| format.reading_enabled())
+        || (cfg!(feature = "heic") && heif)
+}
+
+fn directory_images(path: &Path) -> Option<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!(
+                "{} Unable to read directory '{}': {}",
+                "!".yellow(),
+                path.display(),
+                err
+            );
+            return None;
+        }
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.is_file() && is_image(file))
+        .collect();
+    if files.is_empty() {
+        eprintln!(
+            "{} No images found in directory '{}'",
+            "!".yellow(),
+            path.display()
         );
-        let confirm = inquire::Confirm::new(&message);
-        args.overwrite = confirm.prompt().unwrap_or(false);
-        if !args.overwrite {
-            let input = inquire::Text::new("Please, enter new name for the output wallpaper:")
-                .with_validator(MinLengthValidator::new(1));
-            let name = input.prompt().unwrap_or(args.output);
-            args.output = output_parser(&name).expect("function does not return Err");
+        return None;
+    }
+    sort_by_name(&mut files);
+    Some(files)
+}
+
+fn pattern_matches(pattern: &str) -> Option<Vec<PathBuf>> {
+    let paths = match glob::glob(pattern) {
+        Ok(paths) => paths,
+        Err(err) => {
+            eprintln!("{} Invalid pattern '{}': {}", "!".yellow(), pattern, err);
+            return None;
         }
+    };
+    // Unreadable directories are skipped like the shell would
+    let mut files: Vec<PathBuf> = paths
+        .filter_map(Result::ok)
+        .filter(|file| file.is_file())
+        .collect();
+    if files.is_empty() {
+        eprintln!("{} No files match pattern '{}'", "!".yellow(), pattern);
+        return None;
     }
-    config.normalize();
+    sort_by_name(&mut files);
+    Some(files)
+}
 
-    let virtual_resolution = config.bounds.resolution();
-    let mut output = RgbImage::new(virtual_resolution.0, virtual_resolution.1);
+/// Appends the default extension to `name` if needed and puts it into `dir`
+fn output_path(dir: Option<&Path>, name: &str) -> String {
+    let name = output_parser(name).expect("function does not return Err");
+    match dir {
+        Some(dir) => dir.join(name).to_string_lossy().into_owned(),
+        None => name,
+    }
+}
 
-    for (idx, arg) in args.images.iter_mut().enumerate() {
-        let display = config
-            .displays
-            .get(idx)
-            .expect("length of images equals to the one of displays");
-        let display_res = display.bounds.resolution();
-        match arg {
-            WallpaperArgument::Image(file, filename) => {
-                let reader = match ImageReader::new(BufReader::new(file)).with_guessed_format() {
-                    Ok(reader) => reader,
-                    Err(err) => {
-                        println!(
-                            "{} Unable to detect image format for '{}': {}",
-                            "!".yellow(),
-                            filename,
-                            err
-                        );
-                        continue;
-                    }
-                };
-                let image = match reader.decode() {
-                    Ok(image) => image,
-                    Err(err) => {
-                        println!(
-                            "{} Unable to decode image '{}': {}",
-                            "!".yellow(),
-                            filename,
-                            err
-                        );
-                        continue;
-                    }
-                };
+/// Creates an output directory, asking for confirmation first unless `force` is set
+fn create_output_dir(dir: &Path, force: bool) -> bool {
+    if !force {
+        let message = format!(
+            "Output directory '{}' doesn't exist. Create it?",
+            dir.display().to_string().yellow()
+        );
+        if !inquire::Confirm::new(&message).prompt().unwrap_or(false) {
+            return false;
+        }
+    }
+    if let Err(err) = std::fs::create_dir_all(dir) {
+        eprintln!("{} {}", "! Unable to create output directory:".red(), err);
+        return false;
+    }
+    true
+}
 
-                let mut resizer = Resizer::new();
-                let cropping = match args.mode {
-                    ResizeMode::Stretch => SrcCropping::None,
-                    ResizeMode::Fill => SrcCropping::FitIntoDestination((0.5, 0.5)),
-                    ResizeMode::Fit => SrcCropping::None,
-                };
-                let dest_res = match args.mode {
-                    ResizeMode::Stretch | ResizeMode::Fill => (display_res.0, display_res.1),
-                    ResizeMode::Fit => {
-                        let width_ratio = image.width() as f32 / display_res.0 as f32;
-                        let height_ratio = image.height() as f32 / display_res.1 as f32;
-                        if width_ratio - height_ratio > f32::EPSILON {
-                            (
-                                display_res.0,
-                                (image.height() as f32 / width_ratio).round() as u32,
-                            )
-                        } else {
-                            (
-                                (image.width() as f32 / height_ratio).round() as u32,
-                                display_res.1,
-                            )
-                        }
-                    }
-                };
-                let mut destination =
-                    DynamicImage::ImageRgb8(RgbImage::new(dest_res.0, dest_res.1));
-                if let Err(err) = resizer.resize(
-                    &image,
-                    &mut destination,
-                    &ResizeOptions {
-                        cropping,
-                        ..Default::default()
-                    },
-                ) {
-                    println!(
-                        "{} Unable to resize image '{}': {}",
-                        "!".yellow(),
-                        filename,
-                        err
-                    );
-                    continue;
-                }
+/// Makes sure the directory of `output` exists and takes new files before anything is rendered,
+/// offering to create it like --output-dir
+fn check_output_dir(output: &str, force: bool) -> bool {
+    let dir = match Path::new(output).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() && !create_output_dir(dir, force) {
+        return false;
+    }
+    // Read-only attributes and ACLs don't reliably tell whether writing works, so try it
+    let probe = dir.join(format!(".wallpaper-aligner-{}.tmp", std::process::id()));
+    match File::create(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            true
+        }
+        Err(err) => {
+            eprintln!(
+                "{} '{}': {}",
+                "! Unable to write to output directory".red(),
+                dir.display(),
+                err
+            );
+            false
+        }
+    }
+}
 
-                let rgb8 = destination.to_rgb8();
-                let mut offset = (display.bounds.min_x as u32, display.bounds.min_y as u32);
-                if dest_res.0 < display_res.0 {
-                    offset.0 += (display_res.0 - dest_res.0) / 2
-                }
-                if dest_res.1 < display_res.1 {
-                    offset.1 += (display_res.1 - dest_res.1) / 2
-                }
-                if let Err(err) = output.copy_from(&rgb8, offset.0, offset.1) {
-                    println!(
-                        "{} Unable to copy image '{}': {}",
+/// Fills in the options that weren't given on the command line from the config file at `path`
+fn apply_config(args: &mut Args, matches: &ArgMatches, path: &Path) -> bool {
+    let config = match Config::load(path) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!(
+                "{} Unable to read config '{}': {}",
+                "!".yellow(),
+                path.display(),
+                err
+            );
+            return false;
+        }
+    };
+    let from_command_line = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    if let Some(output) = config.output.filter(|_| !from_command_line("output")) {
+        args.output = outputs_parser(&output);
+    }
+    if let Some(mode) = config.mode.filter(|_| !from_command_line("mode")) {
+        args.mode = mode;
+    }
+    if let Some(quality) = config.quality.filter(|_| !from_command_line("quality")) {
+        match quality_parser(&quality.to_string()) {
+            Ok(quality) => args.quality = quality,
+            Err(err) => {
+                eprintln!(
+                    "{} Invalid quality in config '{}': {}",
+                    "!".yellow(),
+                    path.display(),
+                    err
+                );
+                return false;
+            }
+        }
+    }
+    if args.images.is_empty() && args.assign.is_empty() {
+        for image in &config.images {
+            match WallpaperArgument::from_str(image) {
+                Ok(argument) => args.images.push(argument),
+                Err(err) => {
+                    eprintln!(
+                        "{} Invalid image '{}' in config '{}': {}",
                         "!".yellow(),
-                        filename,
+                        image,
+                        path.display(),
                         err
                     );
-                    continue;
+                    return false;
                 }
             }
-            WallpaperArgument::Color(color) => {
-                if HexColor::BLACK.eq(color) {
-                    continue;
-                }
-                imageproc::drawing::draw_filled_rect_mut(
-                    &mut output,
-                    Rect::at(display.bounds.min_x, display.bounds.min_y)
-                        .of_size(display_res.0, display_res.1),
-                    Rgb([color.r, color.g, color.b]),
-                );
-            }
         }
     }
-    let picture_compressed = match turbojpeg::compress_image(&output, 100, Subsamp::None) {
-        Ok(compressed) => compressed,
-        Err(err) => {
-            println!("{} {}", "! Unable to compress wallpaper:".red(), err);
-            return;
-        }
-    };
+    true
+}
 
-    match std::fs::write(args.output, picture_compressed) {
-        Ok(_) => {
-            println!("{}", "Done!".green());
+fn detect_displays(args: &Args) -> Option<DisplayConfiguration> {
+    let detected = match &args.layout {
+        Some(layout) if Path::new(layout).is_file() => {
+            wallpaper_aligner::detect_displays_with(&LayoutFile {
+                path: PathBuf::from(layout),
+            })
         }
+        Some(layout) => {
+            let layout = ManualLayout::from_str(layout).expect("layout was checked by the parser");
+            wallpaper_aligner::detect_displays_with(&layout)
+        }
+        None => wallpaper_aligner::detect_displays(args.debug_displays),
+    };
+    let mut config = match detected {
+        Ok(config) => config,
         Err(err) => {
-            println!("{} {}", "! Unable to save wallpaper:".red(), err);
+            eprintln!("{} {}", "! Unable to get display configuration:".red(), err);
+            return None;
         }
     };
+    // Malformed driver data can report displays with inverted bounds, which can't be rendered
+    let count = config.displays.len();
+    config.displays.retain(|display| {
+        let valid = display.bounds.checked_resolution().is_some();
+        if !valid {
+            eprintln!(
+                "{} Ignoring display '{}' with invalid bounds {:?}",
+                "!".yellow(),
+                display.name,
+                display.bounds
+            );
+        }
+        valid
+    });
+    if config.displays.len() != count {
+        config.update_bounds();
+    }
+    if let Some(rotation) = args.rotate_layout {
+        config.sort_by_rotated_position(rotation);
+    }
+    if args.primary_first {
+        config.move_primary_first();
+    }
+    Some(config)
 }
 
-fn get_display_configuration() -> DisplayConfiguration {
-    unsafe extern "system" fn callback(
-        monitor: HMONITOR,
-        _: HDC,
-        rect_ptr: *mut RECT,
-        data: LPARAM,
-    ) -> BOOL {
-        let rect = *rect_ptr;
-        let data = data.0 as *mut (DisplayConfiguration, HashMap<String, String>);
-        let config = &mut (*data).0;
-        config.bounds.min_x = config.bounds.min_x.min(rect.left);
-        config.bounds.max_x = config.bounds.max_x.max(rect.right);
-        config.bounds.min_y = config.bounds.min_y.min(rect.top);
-        config.bounds.max_y = config.bounds.max_y.max(rect.bottom);
-
-        let mut monitor_info: MONITORINFOEXW = std::mem::zeroed();
-        monitor_info.monitorInfo.cbSize = size_of::<MONITORINFOEXW>() as u32;
-        let monitor_info_exw_ptr = &mut monitor_info as *mut _ as *mut MONITORINFO;
-
-        let name = match GetMonitorInfoW(monitor, monitor_info_exw_ptr).ok() {
-            Ok(_) => match convert_string(&monitor_info.szDevice) {
-                Some(str) => (*data)
-                    .1
-                    .get(&str)
-                    .map(|s| s.to_owned())
-                    .unwrap_or("Unknown".to_owned()),
-                None => "Unknown".to_owned(),
-            },
-            Err(err) => {
-                println!("{} Unable to get monitor info: {}", "!".yellow(), err);
-                "Unknown".to_owned()
+/// Asks for an image or a color for every display in turn, or returns None if the user gives up
+fn pick_images(config: &DisplayConfiguration) -> Option<Vec<WallpaperArgument>> {
+    const IMAGE: &str = "Image file";
+    const COLOR: &str = "Color or gradient";
+    const SKIP: &str = "Leave empty";
+    let mut images = Vec::with_capacity(config.displays.len());
+    for (i, display) in config.displays.iter().enumerate() {
+        let (width, height) = display.bounds.resolution();
+        let message = format!("{}. {} ({}x{}):", i + 1, display.name, width, height);
+        let choice = inquire::Select::new(&message, vec![IMAGE, COLOR, SKIP])
+            .prompt()
+            .ok()?;
+        let argument = match choice {
+            IMAGE => {
+                let path = inquire::Text::new("Path to the image:")
+                    .with_validator(|path: &str| {
+                        Ok(if is_image(Path::new(path)) && Path::new(path).is_file() {
+                            Validation::Valid
+                        } else {
+                            Validation::Invalid("There is no image at this path".into())
+                        })
+                    })
+                    .prompt()
+                    .ok()?;
+                open_images(vec![PathBuf::from(path)])?.pop()?
+            }
+            COLOR => {
+                let color = inquire::Text::new("Color, e.g. #202020, navy or grad:#000-#FFF:")
+                    .with_validator(|color: &str| {
+                        Ok(match WallpaperArgument::from_str(color) {
+                            Ok(
+                                WallpaperArgument::Color(_)
+                                | WallpaperArgument::Gradient { .. }
+                                | WallpaperArgument::RadialGradient { .. },
+                            ) => Validation::Valid,
+                            _ => Validation::Invalid("Unable to parse the color".into()),
+                        })
+                    })
+                    .prompt()
+                    .ok()?;
+                WallpaperArgument::from_str(&color).ok()?
             }
+            _ => WallpaperArgument::Skip,
         };
+        images.push(argument);
+    }
+    Some(images)
+}
 
-        config.displays.push(Display {
+/// Lists the assigned arguments in display order, or returns None if a display name is unknown
+fn assigned_images(
+    assignments: &[(String, WallpaperArgument)],
+    config: &DisplayConfiguration,
+) -> Option<Vec<WallpaperArgument>> {
+    if let Some((name, _)) = assignments
+        .iter()
+        .find(|(name, _)| !config.displays.iter().any(|display| display.name == *name))
+    {
+        let names: Vec<_> = config
+            .displays
+            .iter()
+            .map(|display| format!("'{}'", display.name))
+            .collect();
+        eprintln!(
+            "{} No display is named '{}', detected displays are {}.",
+            "!".yellow(),
             name,
-            bounds: rect.into(),
-        });
+            names.join(", ")
+        );
+        return None;
+    }
+    let images = config
+        .displays
+        .iter()
+        .map(|display| {
+            assignments
+                .iter()
+                .rev()
+                .find(|(name, _)| *name == display.name)
+                .map(|(_, argument)| argument.clone())
+                .unwrap_or(WallpaperArgument::Skip)
+        })
+        .collect();
+    Some(images)
+}
 
-        TRUE
+/// Lists the named image sets of a --slideshow, one per subdirectory of `dir`,
+/// or one per `size` images in `dir` if it has no subdirectories
+fn slideshow_sets(dir: &Path, size: usize) -> Vec<(String, Vec<PathBuf>)> {
+    let mut subdirectories: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_dir())
+            .collect(),
+        Err(err) => {
+            eprintln!(
+                "{} Unable to read directory '{}': {}",
+                "!".yellow(),
+                dir.display(),
+                err
+            );
+            return Vec::new();
+        }
+    };
+    if !subdirectories.is_empty() {
+        sort_by_name(&mut subdirectories);
+        return subdirectories
+            .into_iter()
+            .filter_map(|path| {
+                let images = directory_images(&path)?;
+                Some((path.display().to_string(), images))
+            })
+            .collect();
     }
+    let Some(images) = directory_images(dir) else {
+        return Vec::new();
+    };
+    images
+        .chunks(size.max(1))
+        .enumerate()
+        .map(|(i, chunk)| {
+            let first = i * size + 1;
+            let name = format!("images {} to {}", first, first + chunk.len() - 1);
+            (name, chunk.to_vec())
+        })
+        .collect()
+}
 
-    let names = get_monitor_names();
+fn slideshow(args: &mut Args, dir: &Path) {
+    loop {
+        // Sets are listed again on every pass, so images can be added while the slideshow runs
+        let Some(config) = detect_displays(args) else {
+            return;
+        };
+        let mut shown = false;
+        for (name, files) in slideshow_sets(dir, config.displays.len()) {
+            if args.mosaic.is_none() && files.len() != config.displays.len() {
+                eprintln!(
+                    "{} Skipping '{}', it has {} for {}.",
+                    "!".yellow(),
+                    name,
+                    pluralize("image", files.len() as isize, true),
+                    pluralize("display", config.displays.len() as isize, true)
+                );
+                continue;
+            }
+            let Some(images) = open_images(files) else {
+                continue;
+            };
+            args.images = images;
+            eprintln!("Showing '{}'", name);
+            render(args, config.clone());
+            // The wallpaper was just written, so it is ours to replace from now on
+            args.overwrite = true;
+            shown = true;
+            thread::sleep(Duration::from_secs(args.interval));
+        }
+        if !shown {
+            eprintln!(
+                "{} No image set in '{}' matches the displays.",
+                "!".yellow(),
+                dir.display()
+            );
+            return;
+        }
+    }
+}
 
-    let mut data = (DisplayConfiguration::default(), names);
-    match unsafe {
-        EnumDisplayMonitors(
-            None,
-            None,
-            Some(callback),
-            LPARAM(&mut data as *mut _ as isize),
-        )
+/// Lists the directories the images of `arguments` come from
+fn source_directories(arguments: &[WallpaperArgument]) -> Vec<PathBuf> {
+    let mut directories = Vec::new();
+    for argument in arguments {
+        let directory = match argument {
+            WallpaperArgument::Image {
+                source: ImageSource::File(_),
+                filename,
+                ..
+            } => Path::new(filename).parent(),
+            WallpaperArgument::Directory(path) => Some(path.as_path()),
+            WallpaperArgument::Pattern(pattern) => Path::new(pattern).parent(),
+            _ => None,
+        };
+        // Files without a directory are in the current one, and patterns in the directory part
+        // are not resolved, so they fail here
+        let directory = match directory {
+            Some(directory) if directory.as_os_str().is_empty() => Path::new("."),
+            Some(directory) => directory,
+            None => continue,
+        };
+        if let Ok(directory) = std::fs::canonicalize(directory) {
+            if !directories.contains(&directory) {
+                directories.push(directory);
+            }
+        }
     }
-    .ok()
+    directories
+}
+
+/// Opens the file of an image argument again, so the cache sees a changed file as new
+fn reopen(argument: &WallpaperArgument) -> Option<WallpaperArgument> {
+    let mut argument = argument.clone();
+    if let WallpaperArgument::Image {
+        source: ImageSource::File(file),
+        filename,
+        ..
+    } = &mut argument
     {
-        Ok(_) => data.0,
-        Err(err) => {
-            panic!("{} {}", "Unable to get display configuration:".red(), err);
+        match File::open(&*filename) {
+            Ok(reopened) => *file = Arc::new(reopened),
+            Err(err) => {
+                eprintln!(
+                    "{} Unable to open image '{}': {}",
+                    "!".yellow(),
+                    filename,
+                    err
+                );
+                return None;
+            }
         }
     }
+    Some(argument)
 }
 
-fn get_monitor_names() -> HashMap<String, String> {
-    let flags = QDC_ONLY_ACTIVE_PATHS | QDC_VIRTUAL_MODE_AWARE;
-    let mut path_count = 0u32;
-    let mut mode_count = 0u32;
-    match unsafe {
-        GetDisplayConfigBufferSizes(flags, &mut path_count as *mut _, &mut mode_count as *mut _)
+/// Lists the files `render` writes for `config`, so --watch can tell them from changed sources
+/// in the same directory
+fn output_paths(args: &Args, config: &DisplayConfiguration) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = if args.split || args.split_by_name {
+        args.output
+            .iter()
+            .flat_map(|output| {
+                (0..config.displays.len())
+                    .map(|i| split_path(output, config, i, args.split_by_name).into())
+            })
+            .collect()
+    } else {
+        args.output.iter().map(PathBuf::from).collect()
+    };
+    if let Some(theme) = &args.export_theme {
+        paths.push(theme.clone());
     }
-    .ok()
-    {
-        Ok(_) => {}
+    if args.metadata {
+        paths.push(Path::new(&args.output[0]).with_extension("json"));
+    }
+    // Events name files inside the canonical directories being watched, and the files themselves
+    // may not exist yet
+    paths
+        .into_iter()
+        .map(|path| {
+            let directory = match path.parent() {
+                Some(directory) if !directory.as_os_str().is_empty() => directory,
+                _ => Path::new("."),
+            };
+            match (std::fs::canonicalize(directory), path.file_name()) {
+                (Ok(directory), Some(name)) => directory.join(name),
+                _ => path,
+            }
+        })
+        .collect()
+}
+
+fn watch_sources(args: &mut Args, sources: Vec<WallpaperArgument>, layout: &DisplayConfiguration) {
+    let mut watched = sources.clone();
+    watched.extend(args.assign.iter().map(|(_, argument)| argument.clone()));
+    let listener = match watch::SourceChangeListener::new(&source_directories(&watched)) {
+        Ok(listener) => listener,
         Err(err) => {
-            println!(
-                "{} Unable to get display configuration buffer sizes: {}",
+            eprintln!("{} {}", "! Unable to watch for file changes:".red(), err);
+            return;
+        }
+    };
+    // The wallpaper was just written, so it is ours to replace from now on
+    args.overwrite = true;
+    let mut outputs = output_paths(args, layout);
+    eprintln!("Watching source images for changes, press Ctrl+C to stop");
+    loop {
+        if let Err(err) = listener.wait(&outputs) {
+            eprintln!("{} {}", "! Unable to watch for file changes:".red(), err);
+            return;
+        }
+        let Some(images) = sources
+            .iter()
+            .map(reopen)
+            .collect::<Option<Vec<_>>>()
+            .and_then(|images| expand_arguments(images, args.sort_by))
+        else {
+            continue;
+        };
+        let Some(assign) = args
+            .assign
+            .iter()
+            .map(|(name, argument)| Some((name.clone(), reopen(argument)?)))
+            .collect::<Option<Vec<_>>>()
+        else {
+            continue;
+        };
+        args.images = images;
+        args.assign = assign;
+        eprintln!("Source images changed, regenerating wallpaper");
+        if let Some(config) = detect_displays(args) {
+            // Split files are named after the displays, which may have changed
+            outputs = output_paths(args, &config);
+            render(args, config);
+        }
+    }
+}
+
+fn watch_layout(args: &mut Args, images: Vec<WallpaperArgument>, mut fingerprint: String) {
+    let listener = match watch::DisplayChangeListener::new() {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("{} {}", "! Unable to watch for display changes:".red(), err);
+            return;
+        }
+    };
+    // The wallpaper was just written, so it is ours to replace from now on
+    args.overwrite = true;
+    eprintln!("Watching for display changes, press Ctrl+C to stop");
+    loop {
+        if let Err(err) = listener.wait() {
+            eprintln!("{} {}", "! Unable to watch for display changes:".red(), err);
+            return;
+        }
+        // Color depth changes and the like are reported too, but leave the layout as it was
+        let Some(config) = detect_displays(args) else {
+            continue;
+        };
+        let current = config.fingerprint();
+        if current == fingerprint {
+            continue;
+        }
+        fingerprint = current;
+        eprintln!("Display layout changed, regenerating wallpaper");
+        let count = config.displays.len();
+        args.images = images.clone();
+        // Assigned images and mosaics don't go by display order, and --allow-partial handles
+        // missing images on its own
+        if args.assign.is_empty()
+            && args.mosaic.is_none()
+            && !images.is_empty()
+            && images.len() != count
+            && !(args.allow_partial && images.len() < count)
+        {
+            eprintln!(
+                "{} The layout has {} for {}, {}.",
+                "!".yellow(),
+                pluralize("display", count as isize, true),
+                pluralize("image", images.len() as isize, true),
+                if images.len() < count {
+                    "repeating them"
+                } else {
+                    "leaving out the last ones"
+                }
+            );
+            args.images = images.iter().cycle().take(count).cloned().collect();
+        }
+        render(args, config);
+    }
+}
+
+fn render_options(args: &Args) -> RenderOptions {
+    RenderOptions {
+        mode: args.mode,
+        auto_orient: !args.no_auto_orient,
+        brightness: args.brightness,
+        contrast: args.contrast,
+        grayscale: args.grayscale,
+        saturation: args.saturation,
+        square: args.square,
+        focus: args.focus,
+        smart_crop: args.smart_crop,
+        crop: args.crop,
+        flip: Flip {
+            horizontal: args.flip_h,
+            vertical: args.flip_v,
+        },
+        filter: args.filter,
+        pixel_art: args.pixel_art,
+        cache: !args.no_cache,
+        dedupe_sources: args.dedupe_sources,
+        aspect_pad: args.aspect_pad,
+        decode_timeout: args.decode_timeout,
+        decode_concurrency: args.decode_concurrency,
+        timeout: args.timeout,
+        frame: args.frame - 1,
+        backdrop: args.backdrop.clone(),
+        backdrop_sigma: args.backdrop_sigma,
+        pad_color: args.pad_color,
+        background: args.background,
+        gap_color: args.gap_color,
+        fit_background: args.fit_background,
+        fit_blur_sigma: args.fit_blur_sigma,
+        label: args.label,
+        label_color: args.label_color,
+        edge_blend: args.edge_blend,
+        dither: args.dither,
+        mirror: args.mirror.clone(),
+        deterministic: args.deterministic,
+        mosaic: args.mosaic,
+        mosaic_cols: args.mosaic_cols,
+    }
+}
+
+/// Writes the wallpaper and returns whether it went through. Displays that didn't get their image
+/// only count as a failure without --ignore-errors
+fn render(args: &mut Args, mut config: DisplayConfiguration) -> bool {
+    if !args.assign.is_empty() {
+        match assigned_images(&args.assign, &config) {
+            Some(images) => args.images = images,
+            None => return false,
+        }
+    }
+    if args.allow_partial && args.mosaic.is_none() && args.images.len() < config.displays.len() {
+        args.images
+            .resize(config.displays.len(), WallpaperArgument::Skip);
+    }
+    let options = render_options(args);
+    // The layout as detected, before it is moved to the origin and scaled
+    let layout = args.metadata.then(|| config.clone());
+    if let Err(err) = wallpaper_aligner::validate(&config, &args.images, &options) {
+        if let RenderError::ImageCount { .. } = err {
+            eprintln!(
+                "{} {}, please check the arguments and try again.",
                 "!".yellow(),
                 err
             );
-            return HashMap::new();
+            if !args.show_displays {
+                config.show_displays();
+            }
+        } else {
+            eprintln!("{} {}.", "!".yellow(), err);
+        }
+        return false;
+    }
+    if args.on_overlap != OverlapPolicy::LastWins {
+        let overlapping = config.overlapping_displays();
+        for &(first, second) in &overlapping {
+            eprintln!(
+                "{} Displays {} ({}) and {} ({}) overlap, the image of display {} covers the other one.",
+                "!".yellow(),
+                first + 1,
+                config.displays[first].name,
+                second + 1,
+                config.displays[second].name,
+                second + 1
+            );
+        }
+        if args.on_overlap == OverlapPolicy::Error && !overlapping.is_empty() {
+            return false;
         }
     }
+    if args.dry_run {
+        config.normalize().scale(args.scale);
+        print_plan(args, &config, &options);
+        return true;
+    }
+    let split = args.split || args.split_by_name;
+    // The files of every display, for each output
+    let mut split_outputs: Vec<Vec<String>> = Vec::new();
+    if split {
+        for output in &args.output {
+            let mut paths = Vec::new();
+            for i in 0..config.displays.len() {
+                let mut path = split_path(output, &config, i, args.split_by_name);
+                confirm_overwrite(&mut path, args.overwrite, args.output_dir.as_deref());
+                paths.push(path);
+            }
+            split_outputs.push(paths);
+        }
+    } else {
+        let overwrite = args.overwrite;
+        for output in &mut args.output {
+            args.overwrite |= confirm_overwrite(output, overwrite, args.output_dir.as_deref());
+        }
+    }
+    config.normalize().scale(args.scale);
 
-    let mut paths: Vec<DISPLAYCONFIG_PATH_INFO> = Vec::with_capacity(path_count as usize);
-    let mut modes: Vec<DISPLAYCONFIG_MODE_INFO> = Vec::with_capacity(mode_count as usize);
-    unsafe {
-        match QueryDisplayConfig(
-            flags,
-            &mut path_count as *mut _,
-            paths.as_mut_ptr(),
-            &mut mode_count as *mut _,
-            modes.as_mut_ptr(),
-            None,
+    let virtual_resolution = config.bounds.resolution();
+    eprintln!(
+        "Wallpaper size: {}x{}",
+        virtual_resolution.0, virtual_resolution.1
+    );
+    if args.verbose {
+        for (i, display) in config.displays.iter().enumerate() {
+            let (width, height) = display.bounds.resolution();
+            eprintln!(
+                "{}. {} ({}x{}) at {},{}",
+                i + 1,
+                display.name,
+                width,
+                height,
+                display.bounds.min_x,
+                display.bounds.min_y
+            );
+        }
+    }
+    let progress = if args.quiet || !std::io::stderr().is_terminal() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(args.images.len() as u64).with_style(
+            ProgressStyle::with_template("[{bar:30}] {pos}/{len} displays, {wide_msg}")
+                .expect("template is valid")
+                .progress_chars("=> "),
         )
-        .ok()
-        {
-            Ok(_) => {}
-            Err(err) => {
-                println!("Unable to query display config: {}", err);
-                return HashMap::new();
+    };
+    let generated = wallpaper_aligner::generate_wallpaper_with_progress(
+        &config,
+        &args.images,
+        &options,
+        &|name| {
+            progress.set_message(name.to_owned());
+            progress.inc(1);
+        },
+    );
+    progress.finish_and_clear();
+    let wallpaper = match generated {
+        Ok(wallpaper) => wallpaper,
+        Err(err) => {
+            eprintln!("{} {}.", "!".yellow(), err);
+            return false;
+        }
+    };
+    for warning in &wallpaper.warnings {
+        eprintln!("{} {}", "!".yellow(), warning);
+    }
+    let targets = wallpaper_aligner::render_targets(&config, args.images.len(), &options);
+    let failed: usize = wallpaper
+        .failures
+        .iter()
+        .map(|(displays, _)| displays.len())
+        .sum();
+    if failed > 0 {
+        eprintln!(
+            "{} {} of {} failed and show the background instead:",
+            "!".yellow(),
+            failed,
+            pluralize("display", targets.len() as isize, true)
+        );
+        for (displays, err) in &wallpaper.failures {
+            for &idx in displays {
+                eprintln!("  {}. {}: {}", idx + 1, targets[idx].name, err);
             }
         }
-        paths.set_len(path_count as usize);
-        modes.set_len(mode_count as usize);
     }
+    if args.stats {
+        println!("Render statistics:");
+        for (i, (display, line)) in targets.iter().zip(&wallpaper.stats).enumerate() {
+            let (width, height) = display.bounds.resolution();
+            println!(
+                "{}. {} ({}x{}): {}",
+                i + 1,
+                display.name,
+                width,
+                height,
+                line
+            );
+        }
+        println!(
+            "Background: {:.1}% of the canvas",
+            wallpaper.background_percent()
+        );
+    }
+    // Options of one format only matter if at least one output is in that format
+    let lossless = |output: &String| is_png(output) || is_bmp(output);
+    let jpeg = args
+        .output
+        .iter()
+        .any(|output| !lossless(output) && !is_webp(output));
+    if args.comment.is_some() && !jpeg {
+        eprintln!(
+            "{} Comments can only be embedded into JPEG wallpapers",
+            "!".yellow()
+        );
+    }
+    if args.max_size.is_some() && !jpeg {
+        eprintln!(
+            "{} --max-size only applies to JPEG wallpapers",
+            "!".yellow()
+        );
+    }
+    if args.output_bitdepth != 24 && !args.output.iter().any(lossless) {
+        eprintln!(
+            "{} --output-bitdepth only applies to PNG and BMP wallpapers",
+            "!".yellow()
+        );
+    }
+    // Every output is written even if an earlier one fails, each reporting how it went
+    let mut saved = true;
+    if split {
+        for paths in &split_outputs {
+            for (display, path) in config.displays.iter().zip(paths) {
+                let (width, height) = display.bounds.resolution();
+                let image = image::imageops::crop_imm(
+                    &wallpaper.image,
+                    display.bounds.min_x as u32,
+                    display.bounds.min_y as u32,
+                    width,
+                    height,
+                )
+                .to_image();
+                saved &= save_output(&image, path, args).is_some();
+            }
+        }
+    } else {
+        let mut outputs = args.output.iter();
+        let first = outputs.next().expect("there is at least one output");
+        let first_compressed = save_output(&wallpaper.image, first, args);
+        for output in outputs {
+            saved &= save_output(&wallpaper.image, output, args).is_some();
+        }
+        let Some(picture_compressed) = first_compressed else {
+            return false;
+        };
+        if let Some(theme_path) = &args.export_theme {
+            let wallpaper_name = Path::new(first)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("wallpaper.jpg");
+            if let Err(err) = theme::export(theme_path, wallpaper_name, &picture_compressed) {
+                eprintln!("{} {}", "! Unable to export theme:".red(), err);
+                return false;
+            }
+        }
+    }
+    if !saved {
+        return false;
+    }
+    if let Some(layout) = &layout {
+        let path = Path::new(&args.output[0]).with_extension("json");
+        if let Err(err) = std::fs::write(&path, metadata(args, layout)) {
+            eprintln!("{} {}", "! Unable to save metadata:".red(), err);
+            return false;
+        }
+    }
+    eprintln!("{}", "Done!".green());
+    failed == 0 || args.ignore_errors
+}
 
-    let mut result: HashMap<String, String> = HashMap::with_capacity(path_count as usize);
+/// Asks what to do while `path` exists, unless `overwrite` is set: replace it, enter another
+/// name or number it like the next free copy. Returns whether the user agreed to replace it
+fn confirm_overwrite(path: &mut String, mut overwrite: bool, output_dir: Option<&Path>) -> bool {
+    const OVERWRITE: &str = "Overwrite";
+    const RENAME: &str = "Rename";
+    const NUMBER: &str = "Auto-number";
+    while !overwrite && Path::new(path.as_str()).exists() {
+        let message = format!("Output file '{}' already exists.", path.yellow());
+        let choice = inquire::Select::new(&message, vec![OVERWRITE, RENAME, NUMBER])
+            .prompt()
+            .unwrap_or(RENAME);
+        match choice {
+            OVERWRITE => overwrite = true,
+            NUMBER => *path = numbered_path(path),
+            _ => {
+                let input = inquire::Text::new("Please, enter new name for the output wallpaper:")
+                    .with_validator(MinLengthValidator::new(1));
+                if let Ok(name) = input.prompt() {
+                    *path = output_path(output_dir, &name);
+                }
+            }
+        }
+    }
+    overwrite
+}
 
-    for path in &paths {
-        let target_name = unsafe {
-            let mut target_name: DISPLAYCONFIG_TARGET_DEVICE_NAME = std::mem::zeroed();
-            target_name.header = DISPLAYCONFIG_DEVICE_INFO_HEADER {
-                r#type: DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
-                id: path.targetInfo.id,
-                adapterId: path.targetInfo.adapterId,
-                size: size_of::<DISPLAYCONFIG_TARGET_DEVICE_NAME>() as u32,
+/// Returns `path` with the lowest number from 2 up appended that doesn't exist yet,
+/// e.g. wallpaper_2.jpg for wallpaper.jpg
+fn numbered_path(path: &str) -> String {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("wallpaper");
+    (2..)
+        .map(|number| {
+            let file_name = match path.extension().and_then(|extension| extension.to_str()) {
+                Some(extension) => format!("{}_{}.{}", stem, number, extension),
+                None => format!("{}_{}", stem, number),
             };
-            let device_name_header_ptr =
-                &mut target_name as *mut _ as *mut DISPLAYCONFIG_DEVICE_INFO_HEADER;
+            path.with_file_name(file_name)
+        })
+        .find(|candidate| !candidate.exists())
+        .expect("there is always a free number")
+        .to_string_lossy()
+        .into_owned()
+}
 
-            if let Err(err) =
-                WIN32_ERROR(DisplayConfigGetDeviceInfo(device_name_header_ptr) as u32).ok()
-            {
-                println!("Unable to get target name: {}", err);
-                continue;
-            }
+/// Names the file of display `index` for --split by appending its number or name to `output`
+fn split_path(output: &str, config: &DisplayConfiguration, index: usize, by_name: bool) -> String {
+    let path = Path::new(output);
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("wallpaper");
+    let name = &config.displays[index].name;
+    let shared = config
+        .displays
+        .iter()
+        .enumerate()
+        .any(|(i, display)| i != index && display.name == *name);
+    let suffix = if !by_name {
+        (index + 1).to_string()
+    } else if shared {
+        // Identical monitors share a name, so their numbers tell the files apart
+        format!("{}_{}", file_name_safe(name), index + 1)
+    } else {
+        file_name_safe(name)
+    };
+    let file_name = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => format!("{}_{}.{}", stem, suffix, extension),
+        None => format!("{}_{}", stem, suffix),
+    };
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
 
-            target_name
+/// Replaces characters Windows doesn't allow in file names
+fn file_name_safe(name: &str) -> String {
+    name.replace(['\\', '/', ':', '*', '?', '"', '<', '>', '|'], "_")
+}
+
+/// Saves `output` to `path` like `save_wallpaper`, naming the file once it's written if the
+/// wallpaper goes to several
+fn save_output(output: &RgbImage, path: &str, args: &Args) -> Option<Vec<u8>> {
+    let compressed = save_wallpaper(output, path, args)?;
+    if args.output.len() > 1 {
+        eprintln!("Saved '{}', {} bytes", path, compressed.len());
+    }
+    Some(compressed)
+}
+
+/// Compresses `output` in the format `path` asks for and writes it there.
+/// Returns the written bytes, or None after printing why it failed
+fn save_wallpaper(output: &RgbImage, path: &str, args: &Args) -> Option<Vec<u8>> {
+    let picture_compressed = if is_png(path) || is_bmp(path) {
+        let format = if is_png(path) {
+            ImageFormat::Png
+        } else {
+            ImageFormat::Bmp
+        };
+        let mut encoded = Vec::new();
+        let written = if args.output_bitdepth == 32 {
+            let output: RgbaImage = output.convert();
+            output.write_to(&mut Cursor::new(&mut encoded), format)
+        } else {
+            output.write_to(&mut Cursor::new(&mut encoded), format)
         };
+        if let Err(err) = written {
+            eprintln!("{} {}", "! Unable to compress wallpaper:".red(), err);
+            return None;
+        }
+        encoded
+    } else if is_webp(path) {
+        let encoder = webp::Encoder::from_rgb(output.as_raw(), output.width(), output.height());
+        match encoder.encode_simple(args.quality == 100, args.quality as f32) {
+            Ok(webp) => webp.to_vec(),
+            Err(err) => {
+                eprintln!("{} {:?}", "! Unable to compress wallpaper:".red(), err);
+                return None;
+            }
+        }
+    } else if let Some(max_size) = args.max_size {
+        match compress_jpeg_within(output, args, max_size) {
+            Ok(Ok((jpeg, quality))) => {
+                eprintln!(
+                    "Compressed '{}' at quality {} to fit into {} bytes",
+                    path, quality, max_size
+                );
+                jpeg
+            }
+            Ok(Err(smallest)) => {
+                eprintln!(
+                    "{} the wallpaper takes {} bytes even at quality 1, more than {}",
+                    "! Unable to fit into --max-size:".red(),
+                    smallest,
+                    max_size
+                );
+                return None;
+            }
+            Err(err) => {
+                eprintln!("{} {}", "! Unable to compress wallpaper:".red(), err);
+                return None;
+            }
+        }
+    } else {
+        match compress_jpeg(output, args.quality, args) {
+            Ok(jpeg) => jpeg,
+            Err(err) => {
+                eprintln!("{} {}", "! Unable to compress wallpaper:".red(), err);
+                return None;
+            }
+        }
+    };
 
-        let target_friendly_name = match convert_string(&target_name.monitorFriendlyDeviceName) {
-            Some(str) => str.to_owned(),
-            None => {
-                println!("Unable to parse target friendly name to a UTF-8 string");
-                continue;
+    if let Err(err) = std::fs::write(path, &picture_compressed) {
+        eprintln!("{} {}", "! Unable to save wallpaper:".red(), err);
+        return None;
+    }
+    Some(picture_compressed)
+}
+
+fn compress_jpeg(
+    output: &RgbImage,
+    quality: i32,
+    args: &Args,
+) -> Result<Vec<u8>, turbojpeg::Error> {
+    let jpeg = turbojpeg::compress_image(output, quality, Subsamp::None)?;
+    Ok(match &args.comment {
+        Some(comment) => jpeg::insert_comment(&jpeg, comment),
+        None => jpeg.to_vec(),
+    })
+}
+
+/// Binary searches the highest quality up to --quality whose JPEG takes at most `max_size` bytes.
+/// Returns the JPEG and its quality, or the size at quality 1 if nothing fits
+fn compress_jpeg_within(
+    output: &RgbImage,
+    args: &Args,
+    max_size: u64,
+) -> Result<Result<(Vec<u8>, i32), usize>, turbojpeg::Error> {
+    let (mut lowest, mut highest) = (1, args.quality);
+    let mut best = None;
+    let mut smallest = usize::MAX;
+    while lowest <= highest {
+        let quality = (lowest + highest) / 2;
+        let jpeg = compress_jpeg(output, quality, args)?;
+        if jpeg.len() as u64 <= max_size {
+            best = Some((jpeg, quality));
+            lowest = quality + 1;
+        } else {
+            smallest = smallest.min(jpeg.len());
+            highest = quality - 1;
+        }
+    }
+    Ok(best.ok_or(smallest))
+}
+
+/// Prints where every image would be placed on the normalized `config` canvas
+fn print_plan(args: &Args, config: &DisplayConfiguration, options: &RenderOptions) {
+    let (width, height) = config.bounds.resolution();
+    println!(
+        "Would write a {}x{} wallpaper to '{}':",
+        width,
+        height,
+        args.output.join("', '")
+    );
+    let targets = wallpaper_aligner::render_targets(config, args.images.len(), options);
+    for (i, (display, arg)) in targets.iter().zip(&args.images).enumerate() {
+        let display_res = display.bounds.resolution();
+        let placement = match arg {
+            WallpaperArgument::Image {
+                source,
+                filename,
+                mode,
+                rotation,
+                crop,
+                ..
+            } => {
+                let mode = mode.unwrap_or(args.mode);
+                let dest_res = match wallpaper_aligner::planned_resolution(
+                    source,
+                    filename,
+                    display_res,
+                    mode,
+                    *rotation,
+                    crop.or(options.crop),
+                    options,
+                ) {
+                    Ok(dest_res) => dest_res,
+                    Err(err) => {
+                        eprintln!("{} {}", "!".yellow(), err);
+                        continue;
+                    }
+                };
+                let tile = display.bounds.centered(dest_res);
+                let mode = mode.to_possible_value().expect("no resize mode is skipped");
+                format!(
+                    "'{}' in {} mode, resized to {}x{} at {},{}",
+                    filename,
+                    mode.get_name(),
+                    dest_res.0,
+                    dest_res.1,
+                    tile.min_x,
+                    tile.min_y
+                )
+            }
+            WallpaperArgument::Color(color) => format!("color {}", color.display_rgba()),
+            WallpaperArgument::Gradient { stops, .. } => format!(
+                "gradient of {}",
+                pluralize("color", stops.len() as isize, true)
+            ),
+            WallpaperArgument::RadialGradient { stops, .. } => format!(
+                "radial gradient of {}",
+                pluralize("color", stops.len() as isize, true)
+            ),
+            WallpaperArgument::Skip => "skipped".to_owned(),
+            WallpaperArgument::Directory(_)
+            | WallpaperArgument::Pattern(_)
+            | WallpaperArgument::Stdin => {
+                unreachable!("directories, patterns and stdin are expanded before rendering")
             }
         };
+        println!(
+            "{}. {} ({}x{}): {}",
+            i + 1,
+            display.name,
+            display_res.0,
+            display_res.1,
+            placement
+        );
+    }
+}
 
-        let source_name = unsafe {
-            let mut source_name: DISPLAYCONFIG_SOURCE_DEVICE_NAME = std::mem::zeroed();
-            source_name.header = DISPLAYCONFIG_DEVICE_INFO_HEADER {
-                r#type: DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME,
-                size: size_of::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>() as u32,
-                adapterId: path.targetInfo.adapterId,
-                id: path.sourceInfo.id,
-            };
-            let adapter_name_header_ptr =
-                &mut source_name as *mut _ as *mut DISPLAYCONFIG_DEVICE_INFO_HEADER;
+/// Backgrounds of the displays on the --identify image, bright enough for white numbers to stand out
+const IDENTIFY_COLORS: [u32; 8] = [
+    0xC0392B, 0x2980B9, 0x27AE60, 0x8E44AD, 0xD35400, 0x16A085, 0x2C3E50, 0x7F8C8D,
+];
 
-            if let Err(err) =
-                WIN32_ERROR(DisplayConfigGetDeviceInfo(adapter_name_header_ptr) as u32).ok()
-            {
-                println!("Unable to get source name: {}", err);
-                continue;
-            }
+fn identify(args: &Args, mut config: DisplayConfiguration) {
+    if let Some(dir) = &args.output_dir {
+        if !dir.is_dir() && !create_output_dir(dir, args.overwrite) {
+            return;
+        }
+    }
+    let path = output_path(args.output_dir.as_deref(), "identify.jpg");
+    config.normalize();
+    let (width, height) = config.bounds.resolution();
+    let mut output = RgbImage::new(width, height);
+    for (i, display) in config.displays.iter().enumerate() {
+        let [_, r, g, b] = IDENTIFY_COLORS[i % IDENTIFY_COLORS.len()].to_be_bytes();
+        let (display_width, display_height) = display.bounds.resolution();
+        imageproc::drawing::draw_filled_rect_mut(
+            &mut output,
+            Rect::at(display.bounds.min_x, display.bounds.min_y)
+                .of_size(display_width, display_height),
+            Rgb([r, g, b]),
+        );
+        render::draw_number(&mut output, &display.bounds, i + 1, Rgb([255, 255, 255]));
+    }
+    let jpeg = match turbojpeg::compress_image(&output, 90, Subsamp::None) {
+        Ok(compressed) => compressed,
+        Err(err) => {
+            eprintln!("{} {}", "! Unable to compress wallpaper:".red(), err);
+            return;
+        }
+    };
+    if let Err(err) = std::fs::write(&path, &jpeg) {
+        eprintln!("{} {}", "! Unable to save wallpaper:".red(), err);
+        return;
+    }
+    eprintln!(
+        "Saved '{}', set it as a spanned wallpaper to see the number of each display",
+        path
+    );
+}
 
-            source_name
-        };
+/// A display as --displays-json prints it, with its size spelled out for whoever reads the file
+#[derive(Serialize)]
+struct DisplayJson<'a> {
+    #[serde(flatten)]
+    display: &'a Display,
+    width: u32,
+    height: u32,
+}
 
-        let gdi_device_name = match convert_string(&source_name.viewGdiDeviceName) {
-            Some(str) => str.to_owned(),
-            None => {
-                println!("Unable to parse source name to a UTF-8 string");
-                continue;
+/// The layout as --displays-json prints it. Loading it with --layout only needs the bounds,
+/// so the sizes are ignored there
+#[derive(Serialize)]
+struct LayoutJson<'a> {
+    bounds: &'a Rectangle,
+    displays: Vec<DisplayJson<'a>>,
+}
+
+fn displays_json(config: &DisplayConfiguration) -> String {
+    let layout = LayoutJson {
+        bounds: &config.bounds,
+        displays: config
+            .displays
+            .iter()
+            .map(|display| {
+                let (width, height) = display.bounds.resolution();
+                DisplayJson {
+                    display,
+                    width,
+                    height,
+                }
+            })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&layout).expect("display layout always serializes")
+}
+
+/// Bump when fields of the --metadata document are removed or change meaning
+const METADATA_SCHEMA_VERSION: u32 = 1;
+
+fn metadata(args: &Args, layout: &DisplayConfiguration) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let images: Vec<_> = args.images.iter().map(ToString::to_string).collect();
+    let document = serde_json::json!({
+        "schema_version": METADATA_SCHEMA_VERSION,
+        "version": env!("CARGO_PKG_VERSION"),
+        "timestamp": timestamp,
+        "arguments": args
+            .recorded_arguments
+            .clone()
+            .unwrap_or_else(|| std::env::args().skip(1).collect()),
+        "layout": layout,
+        "images": images,
+        "mode": args.mode.to_possible_value().map(|mode| mode.get_name().to_owned()),
+        "quality": args.quality,
+        "scale": args.scale,
+        "output": args.output.join(","),
+    });
+    serde_json::to_string_pretty(&document).expect("JSON values always serialize")
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    arguments: Vec<String>,
+    layout: DisplayConfiguration,
+    images: Vec<String>,
+    mode: Option<ResizeMode>,
+    quality: i32,
+    scale: f64,
+    output: String,
+}
+
+/// Rebuilds the arguments of a render from its --metadata file, along with the layout it was
+/// made for. The images, mode, quality, scale and output are taken from what the render ended
+/// up using, so options that came from a config file or --assign are kept too
+fn load_metadata(path: &Path, current: &Args) -> Option<(Args, DisplayConfiguration)> {
+    let document: Metadata = match std::fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|json| serde_json::from_str(&json).map_err(|err| err.to_string()))
+    {
+        Ok(document) => document,
+        Err(err) => {
+            eprintln!(
+                "{} Unable to read metadata '{}': {}",
+                "!".yellow(),
+                path.display(),
+                err
+            );
+            return None;
+        }
+    };
+    let program = std::env::args().next().unwrap_or_default();
+    let mut args =
+        match Args::try_parse_from(std::iter::once(program).chain(document.arguments.clone())) {
+            Ok(args) => args,
+            Err(err) => {
+                eprintln!(
+                    "{} Unable to use arguments from metadata '{}': {}",
+                    "!".yellow(),
+                    path.display(),
+                    err.kind()
+                );
+                return None;
             }
         };
-
-        result.insert(gdi_device_name, target_friendly_name);
+    args.images = Vec::with_capacity(document.images.len());
+    for (i, image) in document.images.iter().enumerate() {
+        match WallpaperArgument::from_str(image) {
+            // The piped data is gone, a file of that name would be opened instead
+            Ok(WallpaperArgument::Image { filename, .. }) if filename == STDIN_FILENAME => {
+                eprintln!(
+                    "{} Image {} in metadata '{}' was read from standard input and can't be read \
+                     again, please save it to a file and render it from there.",
+                    "!".yellow(),
+                    i + 1,
+                    path.display()
+                );
+                return None;
+            }
+            Ok(argument) => args.images.push(argument),
+            Err(err) => {
+                eprintln!(
+                    "{} Invalid image '{}' in metadata '{}': {}",
+                    "!".yellow(),
+                    image,
+                    path.display(),
+                    err
+                );
+                return None;
+            }
+        }
     }
+    if let Some(mode) = document.mode {
+        args.mode = mode;
+    }
+    args.quality = document.quality;
+    args.scale = document.scale;
+    args.output = outputs_parser(&document.output);
+    // Already resolved into the values above
+    args.config = None;
+    args.assign.clear();
+    args.output_dir = None;
+    args.interactive = false;
+    args.slideshow = None;
+    args.from_metadata = None;
+    args.recorded_arguments = Some(document.arguments);
+    args.overwrite |= current.overwrite;
+    args.color = current.color;
+    Some((args, document.layout))
+}
 
-    result
+/// Bump when fields of the --probe document are removed or change meaning
+const PROBE_SCHEMA_VERSION: u32 = 1;
+
+fn probe(config: &DisplayConfiguration) -> String {
+    let displays: Vec<_> = config
+        .displays
+        .iter()
+        .enumerate()
+        .map(|(i, display)| {
+            let (width, height) = display.bounds.resolution();
+            serde_json::json!({
+                "number": i + 1,
+                "name": display.name,
+                "dpi": display.dpi,
+                "primary": display.primary,
+                "bounds": {
+                    "x": display.bounds.min_x,
+                    "y": display.bounds.min_y,
+                    "width": width,
+                    "height": height,
+                },
+            })
+        })
+        .collect();
+    let mut input_formats: Vec<_> = ImageFormat::all()
+        .filter(|format| format.reading_enabled())
+        .filter_map(|format| format.extensions_str().first())
+        .collect();
+    if cfg!(feature = "heic") {
+        input_formats.push(&"heic");
+    }
+    let document = serde_json::json!({
+        "schema_version": PROBE_SCHEMA_VERSION,
+        "version": env!("CARGO_PKG_VERSION"),
+        "displays": displays,
+        "formats": {
+            "input": input_formats,
+            "output": ["jpg", "png", "webp", "bmp"],
+        },
+    });
+    serde_json::to_string_pretty(&document).expect("JSON values always serialize")
 }
 
-fn convert_string(vec: &[u16]) -> Option<String> {
-    let os_string = match vec.iter().position(|c| *c == 0) {
-        Some(len) => OsString::from_wide(&vec[0..len]),
-        None => OsString::from_wide(&vec[0..vec.len()]),
+/// Compares the size of the wallpaper at `path` with the one `config` would get at `scale`
+fn verify(config: &DisplayConfiguration, path: &Path, scale: f64) -> i32 {
+    let dimensions = ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(ImageError::IoError)
+        .and_then(|reader| reader.into_dimensions());
+    let dimensions = match dimensions {
+        Ok(dimensions) => dimensions,
+        Err(err) => {
+            eprintln!(
+                "{} Unable to read wallpaper '{}': {}",
+                "!".red(),
+                path.display(),
+                err
+            );
+            return 2;
+        }
     };
-    os_string.to_str().map(|s| s.to_owned())
+    let expected = config.normalized().scale(scale).bounds.resolution();
+    if dimensions == expected {
+        println!("Wallpaper matches the display layout");
+        0
+    } else {
+        println!(
+            "Wallpaper is {}x{}, but the display layout needs {}x{}",
+            dimensions.0, dimensions.1, expected.0, expected.1
+        );
+        1
+    }
+}
+
+fn detect_changes(config: &DisplayConfiguration, state_file: &Path, invert: bool) -> i32 {
+    let fingerprint = config.fingerprint();
+    let previous = std::fs::read_to_string(state_file).ok();
+    let changed = previous.as_deref().map(str::trim) != Some(fingerprint.as_str());
+    if let Err(err) = std::fs::write(state_file, &fingerprint) {
+        eprintln!("{} {}", "! Unable to save layout fingerprint:".red(), err);
+        return 2;
+    }
+
+    if changed {
+        println!("Display layout changed");
+    } else {
+        println!("Display layout unchanged");
+    }
+    if changed != invert {
+        0
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wallpaper_aligner::backend::DisplayBackend;
+
+    use super::*;
+
+    #[test]
+    fn displays_json_has_sizes_and_loads_as_layout() {
+        let config = DisplayConfiguration::from_displays(vec![
+            Display {
+                name: "Left".to_owned(),
+                bounds: Rectangle::from_xywh(-1080, 0, 1080, 1920),
+                dpi: 96,
+                primary: false,
+            },
+            Display {
+                name: "Main".to_owned(),
+                bounds: Rectangle::from_xywh(0, 0, 2560, 1440),
+                dpi: 144,
+                primary: true,
+            },
+        ]);
+        let json = displays_json(&config);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["displays"][0]["width"], 1080);
+        assert_eq!(value["displays"][0]["height"], 1920);
+        assert_eq!(value["displays"][1]["width"], 2560);
+        assert_eq!(value["displays"][1]["height"], 1440);
+
+        let path = std::env::temp_dir().join(format!(
+            "wallpaper-aligner-test-{}-layout.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, &json).unwrap();
+        let loaded = LayoutFile { path: path.clone() }.detect();
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.displays.len(), 2);
+        assert_eq!(loaded.displays[0].name, "Left");
+        assert_eq!(loaded.displays[0].bounds.min_x, -1080);
+        assert_eq!(loaded.displays[1].bounds.resolution(), (2560, 1440));
+        assert!(loaded.displays[1].primary);
+    }
+
+    #[test]
+    fn layouts_without_dpi_are_not_scaled() {
+        let path = std::env::temp_dir().join(format!(
+            "wallpaper-aligner-test-{}-layout-without-dpi.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"{
+                "bounds": {"min_x": 0, "max_x": 1920, "min_y": 0, "max_y": 1080},
+                "displays": [{
+                    "name": "Main",
+                    "bounds": {"min_x": 0, "max_x": 1920, "min_y": 0, "max_y": 1080}
+                }]
+            }"#,
+        )
+        .unwrap();
+        let loaded = LayoutFile { path: path.clone() }.detect();
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.displays[0].dpi, 96);
+        assert_eq!(loaded.displays[0].scale_percent(), 100);
+    }
+
+    #[test]
+    fn output_bitdepth_sets_the_channels_of_png_and_bmp() {
+        let output = RgbImage::from_pixel(4, 2, Rgb([10, 20, 30]));
+        for (name, bits, color) in [
+            ("png", "24", image::ColorType::Rgb8),
+            ("png", "32", image::ColorType::Rgba8),
+            ("bmp", "24", image::ColorType::Rgb8),
+            ("bmp", "32", image::ColorType::Rgba8),
+        ] {
+            let path = std::env::temp_dir().join(format!(
+                "wallpaper-aligner-test-{}-bitdepth.{}",
+                std::process::id(),
+                name
+            ));
+            let path = path.to_str().unwrap();
+            let args = Args::try_parse_from([
+                "wallpaper-aligner",
+                "--output-bitdepth",
+                bits,
+                "-o",
+                path,
+                "#000",
+            ])
+            .unwrap();
+            save_wallpaper(&output, path, &args).unwrap();
+            let saved = image::open(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(saved.color(), color, "{} at {} bits", name, bits);
+            assert_eq!(saved.to_rgb8(), output);
+        }
+    }
+
+    #[test]
+    fn metadata_of_stdin_images_is_not_loaded() {
+        let config = DisplayConfiguration::from_displays(vec![Display {
+            name: "Main".to_owned(),
+            bounds: Rectangle::from_xywh(0, 0, 1920, 1080),
+            dpi: 96,
+            primary: true,
+        }]);
+        let mut args = Args::try_parse_from(["wallpaper-aligner", "--metadata", "#000"]).unwrap();
+        args.recorded_arguments = Some(vec!["--metadata".to_owned(), "#000".to_owned()]);
+        let path = std::env::temp_dir().join(format!(
+            "wallpaper-aligner-test-{}-metadata.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, metadata(&args, &config)).unwrap();
+        let loaded = load_metadata(&path, &args);
+        assert_eq!(loaded.unwrap().0.images.len(), 1);
+
+        args.images[0] = WallpaperArgument::Image {
+            source: ImageSource::Memory(Vec::new().into()),
+            filename: STDIN_FILENAME.to_owned(),
+            background: None,
+            mode: None,
+            rotation: 0,
+            crop: None,
+            flip: Flip::default(),
+            tint: None,
+        };
+        std::fs::write(&path, metadata(&args, &config)).unwrap();
+        let loaded = load_metadata(&path, &args);
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.is_none());
+    }
+
+    #[test]
+    fn directories_sort_by_capture_then_modification_time() {
+        let dir = std::env::temp_dir().join(format!(
+            "wallpaper-aligner-test-{}-sort-by-date",
+            std::process::id()
+        ));
+        std::fs::create_dir(&dir).unwrap();
+        let jpeg = turbojpeg::compress_image(&RgbImage::new(8, 8), 90, Subsamp::None).unwrap();
+        let taken = |date| {
+            let tiff = exif::tests::tiff_with_date(date, false);
+            jpeg::tests::insert_exif(&jpeg, 2, &tiff)
+        };
+        std::fs::write(dir.join("a.jpg"), taken("2021:05:01 12:00:00")).unwrap();
+        std::fs::write(dir.join("b.jpg"), taken("2020:05:01 12:00:00")).unwrap();
+        // Without EXIF data, one modified before and one after the photos were taken
+        std::fs::write(dir.join("c.jpg"), &*jpeg).unwrap();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        let d = dir.join("d.png");
+        RgbImage::new(8, 8).save(&d).unwrap();
+        File::options()
+            .write(true)
+            .open(&d)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let mut files = directory_images(&dir).unwrap();
+        sort_by_date(&mut files);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|file| file.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["d.png", "b.jpg", "a.jpg", "c.jpg"]);
+    }
+
+    #[test]
+    fn image_sources_conflict_with_each_other() {
+        let sources: [&[&str]; 5] = [
+            &["--slideshow", "shots"],
+            &["--interactive"],
+            &["--from-metadata", "wallpaper.json"],
+            &["--assign", "Main=#000"],
+            // Last, as everything after the first image counts as an image too
+            &["#000"],
+        ];
+        for (i, first) in sources.iter().enumerate() {
+            for second in &sources[i + 1..] {
+                let arguments = ["wallpaper-aligner"].iter().chain(*first).chain(*second);
+                let err = Args::try_parse_from(arguments).unwrap_err();
+                assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+                // Both options are named, the list of images by its placeholder
+                let message = err.to_string();
+                for source in [first[0], second[0]] {
+                    let name = if source.starts_with("--") {
+                        source
+                    } else {
+                        "[IMAGES]"
+                    };
+                    assert!(message.contains(name), "{} in {}", name, message);
+                }
+            }
+        }
+        assert!(Args::try_parse_from([
+            "wallpaper-aligner",
+            "--assign",
+            "A=#000",
+            "--assign",
+            "B=#FFF"
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn watch_ignores_every_file_a_render_writes() {
+        let config = DisplayConfiguration::from_displays(vec![
+            Display {
+                name: "Left".to_owned(),
+                bounds: Rectangle::from_xywh(0, 0, 1920, 1080),
+                dpi: 96,
+                primary: true,
+            },
+            Display {
+                name: "Right".to_owned(),
+                bounds: Rectangle::from_xywh(1920, 0, 1920, 1080),
+                dpi: 96,
+                primary: false,
+            },
+        ]);
+        let current = std::fs::canonicalize(".").unwrap();
+        let args =
+            Args::try_parse_from(["wallpaper-aligner", "--metadata", "#000", "#FFF"]).unwrap();
+        assert_eq!(
+            output_paths(&args, &config),
+            [
+                current.join("wallpaper.jpg"),
+                current.join("wallpaper.json")
+            ]
+        );
+        let args = Args::try_parse_from([
+            "wallpaper-aligner",
+            "--metadata",
+            "-o",
+            "wall.png,wall",
+            "#000",
+            "#FFF",
+        ])
+        .unwrap();
+        assert_eq!(
+            output_paths(&args, &config),
+            [
+                current.join("wall.png"),
+                current.join("wall.jpg"),
+                current.join("wall.json")
+            ]
+        );
+        let args = Args::try_parse_from(["wallpaper-aligner", "--split", "#000", "#FFF"]).unwrap();
+        assert_eq!(
+            output_paths(&args, &config),
+            [
+                current.join("wallpaper_1.jpg"),
+                current.join("wallpaper_2.jpg")
+            ]
+        );
+    }
 }
@@ -0,0 +1,543 @@
+use ab_glyph::Font;
+use fast_image_resize::{
+    CpuExtensions, FilterType, ResizeAlg, ResizeError, ResizeOptions, Resizer, SrcCropping,
+};
+use image::{DynamicImage, Rgb, RgbImage};
+
+use crate::display::{Edge, Rectangle};
+
+/// Creates a resizer, optionally restricted to the scalar code path so the results don't depend
+/// on the SIMD extensions of the CPU
+pub fn resizer(deterministic: bool) -> Resizer {
+    let mut resizer = Resizer::new();
+    if deterministic {
+        // SAFETY: the scalar code path doesn't use any CPU extensions
+        unsafe { resizer.set_cpu_extensions(CpuExtensions::None) };
+    }
+    resizer
+}
+
+/// Converts any decoded image into RGB8, compositing transparent pixels over `background`
+pub fn flatten(image: DynamicImage, background: Rgb<u8>) -> RgbImage {
+    if !image.color().has_alpha() {
+        return image.into_rgb8();
+    }
+
+    let rgba = image.into_rgba8();
+    let mut rgb = RgbImage::new(rgba.width(), rgba.height());
+    for (src, dst) in rgba.pixels().zip(rgb.pixels_mut()) {
+        let alpha = src[3] as u32;
+        for c in 0..3 {
+            dst[c] =
+                ((src[c] as u32 * alpha + background[c] as u32 * (255 - alpha) + 127) / 255) as u8;
+        }
+    }
+    rgb
+}
+
+/// Blends `color` with the given `alpha` over the `bounds` region of `output`
+pub fn blend_fill(output: &mut RgbImage, bounds: &Rectangle, color: Rgb<u8>, alpha: u8) {
+    let alpha = alpha as u32;
+    let (width, height) = bounds.resolution();
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = output.get_pixel_mut(bounds.min_x as u32 + x, bounds.min_y as u32 + y);
+            for c in 0..3 {
+                pixel[c] =
+                    ((color[c] as u32 * alpha + pixel[c] as u32 * (255 - alpha) + 127) / 255) as u8;
+            }
+        }
+    }
+}
+
+/// Fills `bounds` of `output` with a gradient evenly spaced through `stops`, changing in the
+/// direction of `angle` degrees clockwise from left-to-right. With `dither`, the steps between
+/// levels are broken up with an ordered dither pattern
+pub fn fill_gradient(
+    output: &mut RgbImage,
+    bounds: &Rectangle,
+    stops: &[Rgb<u8>],
+    angle: f32,
+    dither: bool,
+) {
+    let (width, height) = bounds.resolution();
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let span = (width.saturating_sub(1) as f32, height.saturating_sub(1) as f32);
+    // Opposite corners project the furthest, so they get the first and the last stop
+    let extent = (cos.abs() * span.0 + sin.abs() * span.1).max(1.0);
+    for y in 0..height {
+        for x in 0..width {
+            let projection = (x as f32 - span.0 / 2.0) * cos + (y as f32 - span.1 / 2.0) * sin;
+            let t = (projection / extent + 0.5).clamp(0.0, 1.0);
+            let color = blend(stops, t, threshold(x, y, dither));
+            output.put_pixel(bounds.min_x as u32 + x, bounds.min_y as u32 + y, color);
+        }
+    }
+}
+
+/// Fills `bounds` of `output` with a gradient evenly spaced through `stops` from `center`, given
+/// as fractions of the width and height, out to the furthest corner. Dithered like `fill_gradient`
+pub fn fill_radial_gradient(
+    output: &mut RgbImage,
+    bounds: &Rectangle,
+    stops: &[Rgb<u8>],
+    center: (f32, f32),
+    dither: bool,
+) {
+    let (width, height) = bounds.resolution();
+    let center = (
+        center.0 * width.saturating_sub(1) as f32,
+        center.1 * height.saturating_sub(1) as f32,
+    );
+    let corner = (
+        center.0.max(width.saturating_sub(1) as f32 - center.0),
+        center.1.max(height.saturating_sub(1) as f32 - center.1),
+    );
+    let radius = corner.0.hypot(corner.1).max(1.0);
+    for y in 0..height {
+        for x in 0..width {
+            let distance = (x as f32 - center.0).hypot(y as f32 - center.1);
+            let t = (distance / radius).min(1.0);
+            let color = blend(stops, t, threshold(x, y, dither));
+            output.put_pixel(bounds.min_x as u32 + x, bounds.min_y as u32 + y, color);
+        }
+    }
+}
+
+/// Picks the color at `t` between 0 and 1 of a gradient evenly spaced through `stops`,
+/// adding `threshold` to the channels before they are rounded
+fn blend(stops: &[Rgb<u8>], t: f32, threshold: f32) -> Rgb<u8> {
+    let position = t * (stops.len() - 1) as f32;
+    let index = (position.floor() as usize).min(stops.len() - 2);
+    let t = position - index as f32;
+    let (from, to) = (stops[index], stops[index + 1]);
+    Rgb(std::array::from_fn(|c| {
+        let value = from[c] as f32 + (to[c] as f32 - from[c] as f32) * t;
+        (value + threshold).round().clamp(0.0, 255.0) as u8
+    }))
+}
+
+/// 8x8 Bayer matrix, the order in which pixels of a tile cross the dithering threshold
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Offset between -0.5 and 0.5 for rounding the pixel at `x`, `y` with an ordered dither, so a
+/// level between two others turns into a mix of both rather than the nearer one. Zero without
+/// `dither`
+fn threshold(x: u32, y: u32, dither: bool) -> f32 {
+    if !dither {
+        return 0.0;
+    }
+    (BAYER[y as usize % 8][x as usize % 8] as f32 + 0.5) / 64.0 - 0.5
+}
+
+/// Turns `image` clockwise by `degrees`, which is one of 0, 90, 180 or 270
+pub fn rotate(image: RgbImage, degrees: u32) -> RgbImage {
+    match degrees {
+        90 => image::imageops::rotate90(&image),
+        180 => image::imageops::rotate180(&image),
+        270 => image::imageops::rotate270(&image),
+        _ => image,
+    }
+}
+
+/// Moves every pixel of `image` away from or toward its own gray by `factor`, 0 being grayscale
+pub fn saturate(image: &mut RgbImage, factor: f32) {
+    for pixel in image.pixels_mut() {
+        // Same weights as image::imageops::grayscale
+        let gray = 0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32;
+        for c in 0..3 {
+            pixel[c] = (gray + (pixel[c] as f32 - gray) * factor)
+                .round()
+                .clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Lit segments of the digits 0 to 9, from bit 0 to 6: top, top right, bottom right, bottom,
+/// bottom left, top left and middle
+const SEGMENTS: [u8; 10] = [0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07, 0x7F, 0x6F];
+
+/// Draws `number` with seven-segment digits as large as fits comfortably in the middle of `bounds`
+pub fn draw_number(output: &mut RgbImage, bounds: &Rectangle, number: usize, color: Rgb<u8>) {
+    let digits: Vec<usize> = number
+        .to_string()
+        .bytes()
+        .map(|digit| (digit - b'0') as usize)
+        .collect();
+    let (width, height) = bounds.resolution();
+    // Digits are half as wide as they are tall with a fifth of their height between them,
+    // so they take up (7 * count - 2) / 10 of their height in width, which is kept under 80%
+    let count = digits.len() as u32;
+    let digit_height = (height / 3).min(8 * width / (7 * count - 2)).max(10);
+    let digit_width = digit_height / 2;
+    let thickness = (digit_height / 10).max(1);
+    let gap = digit_height / 5;
+    let total_width = count * digit_width + (count - 1) * gap;
+    let left = bounds.min_x + width.saturating_sub(total_width) as i32 / 2;
+    let top = bounds.min_y + height.saturating_sub(digit_height) as i32 / 2;
+
+    let (w, h, t) = (digit_width as i32, digit_height as i32, thickness as i32);
+    let half = h / 2;
+    // x, y, width and height of each segment relative to the top left corner of its digit
+    let segments = [
+        (t, 0, w - 2 * t, t),
+        (w - t, t, t, half - t),
+        (w - t, half, t, half - t),
+        (t, h - t, w - 2 * t, t),
+        (0, half, t, half - t),
+        (0, t, t, half - t),
+        (t, half - t / 2, w - 2 * t, t),
+    ];
+    for (i, digit) in digits.into_iter().enumerate() {
+        let x = left + i as i32 * (w + gap as i32);
+        for (segment, &(sx, sy, sw, sh)) in segments.iter().enumerate() {
+            if SEGMENTS[digit] & (1 << segment) == 0 || sw <= 0 || sh <= 0 {
+                continue;
+            }
+            imageproc::drawing::draw_filled_rect_mut(
+                output,
+                imageproc::rect::Rect::at(x + sx, top + sy).of_size(sw as u32, sh as u32),
+                color,
+            );
+        }
+    }
+}
+
+/// Writes `text` into the top left corner of `bounds`, in `color` or otherwise in white or black,
+/// whichever stands out more from the pixels under it
+pub fn draw_label(
+    output: &mut RgbImage,
+    bounds: &Rectangle,
+    text: &str,
+    color: Option<Rgb<u8>>,
+    font: &impl Font,
+) {
+    let (_, height) = bounds.resolution();
+    let scale = (height as f32 / 40.0).max(16.0);
+    let margin = (scale / 2.0) as i32;
+    let (x, y) = (bounds.min_x + margin, bounds.min_y + margin);
+    let color = color.unwrap_or_else(|| {
+        let (text_width, text_height) = imageproc::drawing::text_size(scale, font, text);
+        let area = Rectangle::from_xywh(x, y, text_width, text_height);
+        let Some(area) = area.intersection(bounds) else {
+            return Rgb([255, 255, 255]);
+        };
+        let mut luma = 0u64;
+        for py in area.min_y..area.max_y {
+            for px in area.min_x..area.max_x {
+                let pixel = output.get_pixel(px as u32, py as u32);
+                luma += 2126 * pixel[0] as u64 + 7152 * pixel[1] as u64 + 722 * pixel[2] as u64;
+            }
+        }
+        // Same weights as image::imageops::grayscale, scaled by 10000
+        if luma / (area.area() * 10000) > 127 {
+            Rgb([0, 0, 0])
+        } else {
+            Rgb([255, 255, 255])
+        }
+    });
+    imageproc::drawing::draw_text_mut(output, color, x, y, scale, font, text);
+}
+
+/// Crops the largest centered square out of `image`
+pub fn crop_square(image: &RgbImage) -> RgbImage {
+    let side = image.width().min(image.height());
+    let x = (image.width() - side) / 2;
+    let y = (image.height() - side) / 2;
+    image::imageops::crop_imm(image, x, y, side, side).to_image()
+}
+
+/// Repeats `image` from the top left corner of a `size` canvas, cutting off the tiles at the right and bottom
+pub fn tile(image: &RgbImage, size: (u32, u32)) -> RgbImage {
+    let mut tiled = RgbImage::new(size.0, size.1);
+    let (tile_width, tile_height) = image.dimensions();
+    for y in (0..size.1).step_by(tile_height as usize) {
+        for x in (0..size.0).step_by(tile_width as usize) {
+            let width = tile_width.min(size.0 - x);
+            let height = tile_height.min(size.1 - y);
+            let tile = image::imageops::crop_imm(image, 0, 0, width, height);
+            image::imageops::replace(&mut tiled, &*tile, x as i64, y as i64);
+        }
+    }
+    tiled
+}
+
+/// Centers `image` on a `size` canvas and repeats its outermost rows and columns out to the edges
+pub fn extend_edges(image: &RgbImage, size: (u32, u32)) -> RgbImage {
+    let (width, height) = image.dimensions();
+    let left = (size.0 - width) / 2;
+    let top = (size.1 - height) / 2;
+    RgbImage::from_fn(size.0, size.1, |x, y| {
+        let x = x.saturating_sub(left).min(width - 1);
+        let y = y.saturating_sub(top).min(height - 1);
+        *image.get_pixel(x, y)
+    })
+}
+
+/// Fades the `width` pixels of `bounds` closest to `edge` toward black within the `start..end` span
+pub fn darken_edge(
+    output: &mut RgbImage,
+    bounds: &Rectangle,
+    edge: Edge,
+    start: i32,
+    end: i32,
+    width: u32,
+) {
+    let (display_width, display_height) = bounds.resolution();
+    let depth = match edge {
+        Edge::Left | Edge::Right => width.min(display_width),
+        Edge::Top | Edge::Bottom => width.min(display_height),
+    } as i32;
+
+    for along in start..end {
+        for d in 0..depth {
+            let (x, y) = match edge {
+                Edge::Left => (bounds.min_x + d, along),
+                Edge::Right => (bounds.max_x - 1 - d, along),
+                Edge::Top => (along, bounds.min_y + d),
+                Edge::Bottom => (along, bounds.max_y - 1 - d),
+            };
+            let factor = (d as f32 + 0.5) / width as f32;
+            let pixel = output.get_pixel_mut(x as u32, y as u32);
+            for c in 0..3 {
+                pixel[c] = (pixel[c] as f32 * factor).round() as u8;
+            }
+        }
+    }
+}
+
+/// Picks the Fill crop centering of `image` for a `target` sized display that keeps the most
+/// gradient energy, so detailed regions win over flat ones
+pub fn smart_crop_centering(image: &RgbImage, target: (u32, u32)) -> (f64, f64) {
+    let (width, height) = image.dimensions();
+    if width < 2 || height < 2 || target.0 == 0 || target.1 == 0 {
+        return (0.5, 0.5);
+    }
+    let target_ratio = target.0 as f64 / target.1 as f64;
+    let crop_sides = width as f64 / height as f64 > target_ratio;
+    let (length, window) = if crop_sides {
+        (
+            width as usize,
+            (height as f64 * target_ratio).round() as usize,
+        )
+    } else {
+        (
+            height as usize,
+            (width as f64 / target_ratio).round() as usize,
+        )
+    };
+    if window == 0 || window >= length {
+        return (0.5, 0.5);
+    }
+
+    let gray = image::imageops::grayscale(image);
+    let mut energy = vec![0u64; length];
+    for y in 0..height - 1 {
+        for x in 0..width - 1 {
+            let value = gray.get_pixel(x, y)[0] as i32;
+            let dx = (gray.get_pixel(x + 1, y)[0] as i32 - value).unsigned_abs();
+            let dy = (gray.get_pixel(x, y + 1)[0] as i32 - value).unsigned_abs();
+            let index = if crop_sides { x } else { y } as usize;
+            energy[index] += (dx + dy) as u64;
+        }
+    }
+
+    // Slide the crop window along the cropped axis, preferring the center on ties
+    let center = (length - window) / 2;
+    let mut sum: u64 = energy[..window].iter().sum();
+    let (mut best_sum, mut best_start) = (sum, 0usize);
+    for start in 1..=length - window {
+        sum = sum + energy[start + window - 1] - energy[start - 1];
+        if sum > best_sum
+            || (sum == best_sum && start.abs_diff(center) < best_start.abs_diff(center))
+        {
+            best_sum = sum;
+            best_start = start;
+        }
+    }
+
+    let fraction = best_start as f64 / (length - window) as f64;
+    if crop_sides {
+        (fraction, 0.5)
+    } else {
+        (0.5, fraction)
+    }
+}
+
+/// Pads `image` with `background` bars on two sides so it gets the aspect ratio of `target`
+pub fn pad_to_aspect(image: &RgbImage, target: (u32, u32), background: Rgb<u8>) -> RgbImage {
+    let (width, height) = image.dimensions();
+    let target_ratio = target.0 as f64 / target.1 as f64;
+    let (padded_width, padded_height) = if width as f64 / height as f64 > target_ratio {
+        (width, (width as f64 / target_ratio).round() as u32)
+    } else {
+        ((height as f64 * target_ratio).round() as u32, height)
+    };
+    let mut padded = RgbImage::from_pixel(padded_width, padded_height, background);
+    image::imageops::replace(
+        &mut padded,
+        image,
+        ((padded_width - width) / 2) as i64,
+        ((padded_height - height) / 2) as i64,
+    );
+    padded
+}
+
+/// Copies the `source` region of `output` onto its `target` region, resizing it if they differ
+pub fn mirror_region(
+    resizer: &mut Resizer,
+    output: &mut RgbImage,
+    source: &Rectangle,
+    target: &Rectangle,
+) -> Result<(), ResizeError> {
+    let (source_width, source_height) = source.resolution();
+    let (target_width, target_height) = target.resolution();
+    let mut region = image::imageops::crop_imm(
+        output,
+        source.min_x as u32,
+        source.min_y as u32,
+        source_width,
+        source_height,
+    )
+    .to_image();
+    if (source_width, source_height) != (target_width, target_height) {
+        let mut resized = DynamicImage::ImageRgb8(RgbImage::new(target_width, target_height));
+        resizer.resize(&DynamicImage::ImageRgb8(region), &mut resized, None)?;
+        region = resized.into_rgb8();
+    }
+    image::imageops::replace(output, &region, target.min_x as i64, target.min_y as i64);
+    Ok(())
+}
+
+/// Scales `image` to fill a `size` canvas and blurs it with the given `sigma`
+pub fn blurred_backdrop(
+    resizer: &mut Resizer,
+    image: RgbImage,
+    size: (u32, u32),
+    sigma: f32,
+) -> Result<RgbImage, ResizeError> {
+    // Blurring a downscaled copy looks the same for large sigmas and is much faster
+    let factor = (sigma / 4.0).max(1.0);
+    let small_width = ((size.0 as f32 / factor).round() as u32).max(1);
+    let small_height = ((size.1 as f32 / factor).round() as u32).max(1);
+
+    let mut small = DynamicImage::ImageRgb8(RgbImage::new(small_width, small_height));
+    resizer.resize(
+        &DynamicImage::ImageRgb8(image),
+        &mut small,
+        &ResizeOptions {
+            cropping: SrcCropping::FitIntoDestination((0.5, 0.5)),
+            ..Default::default()
+        },
+    )?;
+    let blurred = imageproc::filter::gaussian_blur_f32(&small.into_rgb8(), sigma / factor);
+
+    let mut backdrop = DynamicImage::ImageRgb8(RgbImage::new(size.0, size.1));
+    resizer.resize(
+        &DynamicImage::ImageRgb8(blurred),
+        &mut backdrop,
+        &ResizeOptions {
+            algorithm: ResizeAlg::Convolution(FilterType::Bilinear),
+            ..Default::default()
+        },
+    )?;
+    Ok(backdrop.into_rgb8())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dithered_gradients_keep_the_levels_between_steps() {
+        // From level 0 to 1 over 65 pixels, so every 8x8 tile should average its share of the way
+        let bounds = Rectangle::from_xywh(0, 0, 65, 8);
+        let stops = [Rgb([0, 0, 0]), Rgb([1, 1, 1])];
+        let tile_mean = |image: &RgbImage, tile: u32| {
+            let sum: u32 = (tile * 8..tile * 8 + 8)
+                .flat_map(|x| (0..8).map(move |y| (x, y)))
+                .map(|(x, y)| image.get_pixel(x, y)[0] as u32)
+                .sum();
+            sum as f32 / 64.0
+        };
+
+        let mut dithered = RgbImage::new(65, 8);
+        fill_gradient(&mut dithered, &bounds, &stops, 0.0, true);
+        for tile in 0..8 {
+            let expected = (tile * 8) as f32 / 64.0 + 3.5 / 64.0;
+            let mean = tile_mean(&dithered, tile);
+            assert!((mean - expected).abs() < 0.05, "{tile}: {mean} {expected}");
+        }
+
+        // Without dithering every pixel rounds to the nearer level, in a single step
+        let mut banded = RgbImage::new(65, 8);
+        fill_gradient(&mut banded, &bounds, &stops, 0.0, false);
+        assert_eq!(tile_mean(&banded, 3), 0.0);
+        assert_eq!(tile_mean(&banded, 4), 1.0);
+    }
+
+    #[test]
+    fn crop_square_of_landscape_image_keeps_the_middle() {
+        // Columns are numbered by their red channel, so the crop position can be read back
+        let image = RgbImage::from_fn(1920, 1080, |x, _| Rgb([(x % 256) as u8, 0, 0]));
+        let square = crop_square(&image);
+        assert_eq!(square.dimensions(), (1080, 1080));
+        assert_eq!(square.get_pixel(0, 0), image.get_pixel(420, 0));
+        assert_eq!(square.get_pixel(1079, 1079), image.get_pixel(1499, 1079));
+    }
+
+    #[test]
+    fn crop_square_of_portrait_image_keeps_the_middle() {
+        let image = RgbImage::from_fn(1080, 1920, |_, y| Rgb([0, (y % 256) as u8, 0]));
+        let square = crop_square(&image);
+        assert_eq!(square.dimensions(), (1080, 1080));
+        assert_eq!(square.get_pixel(0, 0), image.get_pixel(0, 420));
+    }
+
+    /// A flat gray image with a checkerboard where `detailed` says so
+    fn detailed_where(width: u32, height: u32, detailed: impl Fn(u32, u32) -> bool) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            if detailed(x, y) && (x + y) % 2 == 0 {
+                Rgb([255, 255, 255])
+            } else if detailed(x, y) {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([128, 128, 128])
+            }
+        })
+    }
+
+    #[test]
+    fn smart_crop_prefers_the_detailed_side() {
+        // The last column has no right neighbor to differ from, so the crop may start one early
+        let right = detailed_where(300, 100, |x, _| x >= 200);
+        let (x, y) = smart_crop_centering(&right, (100, 100));
+        assert!(x >= 0.99 && y == 0.5, "{x}, {y}");
+        let left = detailed_where(300, 100, |x, _| x < 100);
+        assert_eq!(smart_crop_centering(&left, (100, 100)), (0.0, 0.5));
+    }
+
+    #[test]
+    fn smart_crop_prefers_the_detailed_rows() {
+        let top = detailed_where(100, 300, |_, y| y < 100);
+        assert_eq!(smart_crop_centering(&top, (100, 100)), (0.5, 0.0));
+        let bottom = detailed_where(100, 300, |_, y| y >= 200);
+        let (x, y) = smart_crop_centering(&bottom, (100, 100));
+        assert!(x == 0.5 && y >= 0.99, "{x}, {y}");
+    }
+
+    #[test]
+    fn smart_crop_keeps_the_center_of_flat_images() {
+        let flat = detailed_where(300, 100, |_, _| false);
+        assert_eq!(smart_crop_centering(&flat, (100, 100)), (0.5, 0.5));
+    }
+}
@@ -0,0 +1,49 @@
+use std::io;
+use std::path::Path;
+
+use crate::cab;
+
+/// Writes a .deskthemepack at `path` that sets `wallpaper` as a spanned desktop background
+pub fn export(path: &Path, wallpaper_name: &str, wallpaper: &[u8]) -> io::Result<()> {
+    let name = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("Wallpaper");
+    // Windows unpacks theme packs into a folder named after the pack
+    let background_dir = format!(
+        r"%USERPROFILE%\AppData\Local\Microsoft\Windows\Themes\{}\DesktopBackground",
+        name
+    );
+    let theme = [
+        "[Theme]".to_owned(),
+        format!("DisplayName={}", name),
+        String::new(),
+        r"[Control Panel\Desktop]".to_owned(),
+        format!(r"Wallpaper={}\{}", background_dir, wallpaper_name),
+        "TileWallpaper=0".to_owned(),
+        // Span the image across all displays
+        "WallpaperStyle=22".to_owned(),
+        String::new(),
+        "[Slideshow]".to_owned(),
+        format!("ImagesRootPath={}", background_dir),
+        format!(r"Item0Path={}\{}", background_dir, wallpaper_name),
+        String::new(),
+        "[VisualStyles]".to_owned(),
+        r"Path=%SystemRoot%\resources\themes\Aero\Aero.msstyles".to_owned(),
+        "ColorStyle=NormalColor".to_owned(),
+        "Size=NormalSize".to_owned(),
+        String::new(),
+        "[MasterThemeSelector]".to_owned(),
+        "MTSM=RJSPBS".to_owned(),
+        String::new(),
+    ]
+    .join("\r\n");
+
+    let theme_name = format!("{}.theme", name);
+    let wallpaper_path = format!(r"DesktopBackground\{}", wallpaper_name);
+    let pack = cab::build(&[
+        (&theme_name, theme.as_bytes()),
+        (&wallpaper_path, wallpaper),
+    ]);
+    std::fs::write(path, pack)
+}
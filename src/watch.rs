@@ -0,0 +1,207 @@
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+#[cfg(windows)]
+use windows::core::{w, Error, Result};
+#[cfg(windows)]
+use windows::Win32::Foundation::{E_ABORT, HWND, LPARAM, LRESULT, WPARAM};
+#[cfg(windows)]
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+#[cfg(windows)]
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetMessageW, KillTimer,
+    RegisterClassW, SetTimer, TranslateMessage, MSG, WINDOW_EX_STYLE, WM_DISPLAYCHANGE, WM_TIMER,
+    WNDCLASSW, WS_OVERLAPPED,
+};
+
+/// Docking and undocking produce a burst of display changes, so they are only reported
+/// once there were none for this long
+const DEBOUNCE_MS: u32 = 2000;
+#[cfg(windows)]
+const TIMER_ID: usize = 1;
+
+/// A hidden window receiving `WM_DISPLAYCHANGE` broadcasts
+#[cfg(windows)]
+pub struct DisplayChangeListener {
+    window: HWND,
+}
+
+#[cfg(windows)]
+impl DisplayChangeListener {
+    pub fn new() -> Result<Self> {
+        unsafe {
+            let instance = GetModuleHandleW(None)?;
+            let class_name = w!("WallpaperAlignerDisplayChangeListener");
+            let class = WNDCLASSW {
+                lpfnWndProc: Some(window_proc),
+                hInstance: instance.into(),
+                lpszClassName: class_name,
+                ..Default::default()
+            };
+            if RegisterClassW(&class) == 0 {
+                return Err(Error::from_win32());
+            }
+            // Message-only windows don't get broadcasts, so this is a regular window that is never shown
+            let window = CreateWindowExW(
+                WINDOW_EX_STYLE::default(),
+                class_name,
+                w!(""),
+                WS_OVERLAPPED,
+                0,
+                0,
+                0,
+                0,
+                None,
+                None,
+                instance,
+                None,
+            )?;
+            Ok(DisplayChangeListener { window })
+        }
+    }
+
+    /// Blocks until the display configuration changes
+    pub fn wait(&self) -> Result<()> {
+        let mut message = MSG::default();
+        loop {
+            match unsafe { GetMessageW(&mut message, None, 0, 0) }.0 {
+                -1 => return Err(Error::from_win32()),
+                0 => return Err(Error::new(E_ABORT, "Message loop was asked to quit")),
+                _ => {}
+            }
+            if message.message == WM_TIMER
+                && message.hwnd == self.window
+                && message.wParam.0 == TIMER_ID
+            {
+                unsafe { KillTimer(self.window, TIMER_ID)? };
+                return Ok(());
+            }
+            unsafe {
+                let _ = TranslateMessage(&message);
+                DispatchMessageW(&message);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for DisplayChangeListener {
+    fn drop(&mut self) {
+        let _ = unsafe { DestroyWindow(self.window) };
+    }
+}
+
+/// Other systems don't tell about display changes, so the layout is checked again periodically,
+/// e.g. to pick up edits of the --layout file
+#[cfg(not(windows))]
+pub struct DisplayChangeListener;
+
+#[cfg(not(windows))]
+impl DisplayChangeListener {
+    pub fn new() -> std::io::Result<Self> {
+        Ok(DisplayChangeListener)
+    }
+
+    /// Blocks until it is time to check the layout again
+    pub fn wait(&self) -> std::io::Result<()> {
+        std::thread::sleep(Duration::from_millis(DEBOUNCE_MS as u64));
+        Ok(())
+    }
+}
+
+/// Watches the directories source images are in
+pub struct SourceChangeListener {
+    _watcher: RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+impl SourceChangeListener {
+    pub fn new(directories: &[PathBuf]) -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        // Directories rather than files are watched, since editors often replace a file when saving it
+        for directory in directories {
+            watcher.watch(directory, RecursiveMode::NonRecursive)?;
+        }
+        Ok(SourceChangeListener {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Blocks until a file other than the `ignored` ones changes and no more changes follow
+    /// for a while
+    pub fn wait(&self, ignored: &[PathBuf]) -> notify::Result<()> {
+        loop {
+            let event = self
+                .events
+                .recv()
+                .map_err(|_| notify::Error::generic("Watcher stopped"))??;
+            if is_source_change(&event, ignored) {
+                break;
+            }
+        }
+        // Copying a batch of images produces a burst of events
+        while self
+            .events
+            .recv_timeout(Duration::from_millis(DEBOUNCE_MS as u64))
+            .is_ok()
+        {}
+        Ok(())
+    }
+}
+
+/// Whether `event` changed a file other than the `ignored` ones, e.g. the wallpaper just written
+fn is_source_change(event: &notify::Event, ignored: &[PathBuf]) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+        && event.paths.iter().any(|path| !ignored.contains(path))
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn window_proc(
+    window: HWND,
+    message: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if message == WM_DISPLAYCHANGE {
+        // Restarting the timer on every change is what debounces them
+        SetTimer(window, TIMER_ID, DEBOUNCE_MS, None);
+        return LRESULT(0);
+    }
+    DefWindowProcW(window, message, wparam, lparam)
+}
+
+#[cfg(test)]
+mod tests {
+    use notify::event::{AccessKind, CreateKind, ModifyKind};
+    use notify::Event;
+
+    use super::*;
+
+    #[test]
+    fn own_outputs_are_not_source_changes() {
+        let wallpaper = PathBuf::from("/photos/wallpaper.jpg");
+        let sidecar = PathBuf::from("/photos/wallpaper.json");
+        let ignored = [wallpaper.clone(), sidecar.clone()];
+        let modified =
+            |path: &PathBuf| Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path.clone());
+        assert!(!is_source_change(&modified(&wallpaper), &ignored));
+        assert!(!is_source_change(&modified(&sidecar), &ignored));
+        let created = Event::new(EventKind::Create(CreateKind::File)).add_path(sidecar);
+        assert!(!is_source_change(&created, &ignored));
+        assert!(is_source_change(
+            &modified(&PathBuf::from("/photos/left.jpg")),
+            &ignored
+        ));
+    }
+
+    #[test]
+    fn reading_a_source_is_not_a_change() {
+        let read = Event::new(EventKind::Access(AccessKind::Any))
+            .add_path(PathBuf::from("/photos/left.jpg"));
+        assert!(!is_source_change(&read, &[]));
+    }
+}